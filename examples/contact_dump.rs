@@ -6,5 +6,5 @@ fn main(){
     let mut header = dtn_tcpcl::ContactHeader::new();
     header.flags(dtn_tcpcl::CAN_TLS).eid("localhost").unwrap();
     let buffer = header.serialize();
-    io::stdout().write(buffer.as_slice()).unwrap();
+    io::stdout().write_all(buffer.as_slice()).unwrap();
 }
\ No newline at end of file
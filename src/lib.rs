@@ -5,31 +5,247 @@
 extern crate byteorder;
 #[macro_use]
 extern crate bitflags;
-#[macro_use]
 extern crate nom;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
-use std::io::{Error, ErrorKind};
-use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, WriteBytesExt};
 
-use nom::{IResult, be_u8, be_u16, be_u64};
+use nom::IResult;
+use nom::bytes::streaming::{tag, take};
+use nom::combinator::{all_consuming, complete, cond, map, map_opt, map_parser, map_res, peek,
+                      value};
+use nom::multi::{length_data, many0};
+use nom::number::streaming::{be_u8, be_u16, be_u32, be_u64};
+#[cfg(feature = "smallvec")]
+use smallvec::SmallVec;
 
+/// Default maximum length of an eid accepted by the Contact Header parser
+pub const MAX_EID_LEN: usize = u16::MAX as usize;
+/// Default maximum length of the data of a XFER_SEGMENT accepted by the parser
+pub const MAX_SEGMENT_LEN: u64 = 1 << 26;
+/// Maximum total length of the Extension Items of a message accepted by the parser
+pub const MAX_EXTENSIONS_LEN: u32 = 1 << 20;
+/// Default maximum number of transfers a `TransferReassembler` keeps in progress
+pub const MAX_TRANSFERS_IN_PROGRESS: usize = 16;
 /// Magic Bytes of the Contact Header
 const HEADER_MAGIC: [u8; 4] = [0x64, 0x74, 0x6e, 0x21];  // dtn!
 /// Length of Contact Header up to the eid
 const CONTACT_HEADER_BASE_LENGTH: usize = 24;
+/// Length of the Contact Header defined in RFC 9174
+const CONTACT_HEADER_RFC9174_LENGTH: usize = 6;
+/// Ratio between advertised keepalive intervals above which they are considered asymmetric
+const KEEPALIVE_ASYMMETRY_RATIO: u32 = 2;
+/// Message type of XFER_SEGMENT
+const MSG_TYPE_XFER_SEGMENT: u8 = 0x01;
+/// Message type of XFER_ACK
+const MSG_TYPE_XFER_ACK: u8 = 0x02;
+/// Message type of XFER_REFUSE
+const MSG_TYPE_XFER_REFUSE: u8 = 0x03;
+/// Message type of KEEPALIVE
+const MSG_TYPE_KEEPALIVE: u8 = 0x04;
+/// Message type of SESS_TERM
+const MSG_TYPE_SESS_TERM: u8 = 0x05;
+/// Message type of MSG_REJECT
+const MSG_TYPE_MSG_REJECT: u8 = 0x06;
+/// Message type of SESS_INIT
+const MSG_TYPE_SESS_INIT: u8 = 0x07;
+/// FNV-1a 64 bit offset basis
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+/// FNV-1a 64 bit prime
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
 
 
-macro_rules! create_error {
-    ( $x:expr ) =>  {
-        {
-            Error::new(ErrorKind::Other, $x)
+#[derive(Debug)]
+/// Errors returned by this crate
+pub enum TcpclError {
+    /// The magic bytes of the Contact Header do not match
+    BadMagic,
+    /// The protocol version is not supported
+    UnsupportedVersion(u8),
+    /// A flags field contains flags that are not defined
+    UnknownFlags(u8),
+    /// The eid is longer than the u16 length field allows
+    EidTooLong(usize),
+    /// The node id is longer than the u16 length field allows
+    NodeIdTooLong(usize),
+    /// The value of an Extension Item is longer than the u16 length field allows
+    ExtensionValueTooLong(usize),
+    /// The Extension Items of a message are longer than `MAX_EXTENSIONS_LEN`
+    ExtensionsTooLong(usize),
+    /// The eid or node id is not valid UTF-8
+    InvalidUtf8,
+    /// The buffer ends before the message is complete
+    Incomplete,
+    /// The message could not be parsed
+    Parse(nom::error::ErrorKind),
+    /// The reason code of a XFER_REFUSE is not defined
+    UnknownRefuseReason(u8),
+    /// The segment mru is zero
+    ZeroSegmentMru,
+    /// The negotiated keepalive interval exceeds the configured maximum
+    KeepaliveTooLong {
+        /// Negotiated keepalive interval in seconds
+        interval: u16,
+        /// Configured maximum in seconds
+        max: u16,
+    },
+    /// Bytes remain after a message that should fill the buffer exactly
+    TrailingBytes(usize),
+    /// A framed Contact Header ends before the number of eid octets given by its length field
+    EidLengthMismatch,
+    /// The data of a XFER_SEGMENT is longer than the configured maximum
+    SegmentTooLong(u64),
+    /// The message type octet is not defined
+    UnknownMessageType(u8),
+    /// A segment with START arrived for a transfer that is already in progress
+    TransferAlreadyStarted(u64),
+    /// A segment without START arrived for a transfer that was not started
+    TransferNotStarted(u64),
+    /// A transfer grew larger than the transfer mru
+    TransferTooLong(u64),
+    /// A transfer was started while the maximum number of transfers is in progress
+    TooManyTransfers(u64),
+    /// Transfer Extension Items were added to a XFER_SEGMENT without START
+    ExtensionsWithoutStart(u64),
+    /// All transfer ids of the session have been used
+    TransferIdsExhausted,
+    /// An I/O error occurred
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for TcpclError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            TcpclError::BadMagic => write!(f, "invalid magic bytes"),
+            TcpclError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}", version)
+            }
+            TcpclError::UnknownFlags(bits) => write!(f, "unknown flags {:#04x}", bits),
+            TcpclError::EidTooLong(len) => write!(f, "eid of {} bytes is to long", len),
+            TcpclError::NodeIdTooLong(len) => write!(f, "node id of {} bytes is to long", len),
+            TcpclError::ExtensionValueTooLong(len) => {
+                write!(f, "extension item value of {} bytes is to long", len)
+            }
+            TcpclError::ExtensionsTooLong(len) => {
+                write!(f, "extension items of {} bytes are to long", len)
+            }
+            TcpclError::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            TcpclError::Incomplete => write!(f, "incomplete message"),
+            TcpclError::Parse(kind) => write!(f, "parse error: {}", kind.description()),
+            TcpclError::UnknownRefuseReason(code) => {
+                write!(f, "unknown XFER_REFUSE reason code {:#04x}", code)
+            }
+            TcpclError::ZeroSegmentMru => write!(f, "segment mru is zero"),
+            TcpclError::KeepaliveTooLong { interval, max } => {
+                write!(f, "keepalive of {}s exceeds maximum of {}s", interval, max)
+            }
+            TcpclError::TrailingBytes(len) => {
+                write!(f, "{} trailing bytes after message", len)
+            }
+            TcpclError::EidLengthMismatch => write!(f, "eid length exceeds the frame"),
+            TcpclError::SegmentTooLong(len) => write!(f, "segment of {} bytes is to long", len),
+            TcpclError::UnknownMessageType(msg_type) => {
+                write!(f, "unknown message type {:#04x}", msg_type)
+            }
+            TcpclError::TransferAlreadyStarted(id) => {
+                write!(f, "transfer {} is already in progress", id)
+            }
+            TcpclError::TransferNotStarted(id) => write!(f, "transfer {} was not started", id),
+            TcpclError::TransferTooLong(id) => {
+                write!(f, "transfer {} exceeds the transfer mru", id)
+            }
+            TcpclError::TooManyTransfers(id) => {
+                write!(f, "transfer {} exceeds the number of transfers in progress", id)
+            }
+            TcpclError::ExtensionsWithoutStart(id) => {
+                write!(f, "segment of transfer {} without START can not carry extensions", id)
+            }
+            TcpclError::TransferIdsExhausted => write!(f, "transfer ids exhausted"),
+            TcpclError::Io(ref e) => write!(f, "I/O error: {}", e),
         }
-    };
+    }
+}
+
+impl std::error::Error for TcpclError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            TcpclError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
+impl From<std::io::Error> for TcpclError {
+    fn from(e: std::io::Error) -> TcpclError {
+        TcpclError::Io(e)
+    }
+}
 
-#[derive(Debug)]
+impl From<nom::Err<TcpclError>> for TcpclError {
+    fn from(e: nom::Err<TcpclError>) -> TcpclError {
+        match e {
+            nom::Err::Incomplete(_) => TcpclError::Incomplete,
+            nom::Err::Error(e) | nom::Err::Failure(e) => e,
+        }
+    }
+}
+
+impl<'a> nom::error::ParseError<&'a [u8]> for TcpclError {
+    fn from_error_kind(_: &'a [u8], kind: nom::error::ErrorKind) -> TcpclError {
+        TcpclError::Parse(kind)
+    }
+
+    fn append(_: &'a [u8], _: nom::error::ErrorKind, other: TcpclError) -> TcpclError {
+        other
+    }
+}
+
+impl<'a> nom::error::FromExternalError<&'a [u8], TcpclError> for TcpclError {
+    fn from_external_error(_: &'a [u8], _: nom::error::ErrorKind, e: TcpclError)
+                           -> TcpclError {
+        e
+    }
+}
+
+impl<'a> nom::error::FromExternalError<&'a [u8], std::string::FromUtf8Error> for TcpclError {
+    fn from_external_error(_: &'a [u8], _: nom::error::ErrorKind,
+                           _: std::string::FromUtf8Error) -> TcpclError {
+        TcpclError::InvalidUtf8
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Protocol variant a Contact Header is encoded for
+pub enum ProtocolVariant {
+    /// The tcpclv4 draft, which carries the session parameters in the Contact Header
+    #[default]
+    Draft,
+    /// RFC 9174, which moves the session parameters to SESS_INIT
+    Rfc9174,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "ContactHeaderRepr"))]
 /// Contact Header
+///
+/// The protocol variant is part of the header, two headers with the same fields but
+/// different variants compare unequal.
+///
+/// With the `serde` feature the serialized form carries the protocol variant next to the
+/// header fields. A missing variant deserializes as `ProtocolVariant::Draft`, an eid longer
+/// than `MAX_EID_LEN` is rejected with `TcpclError::EidTooLong`.
 pub struct ContactHeader {
     version: u8,
     flags: ContactHeaderFlags,
@@ -37,8 +253,46 @@ pub struct ContactHeader {
     segment_mru: u64,
     transfer_mru: u64,
     eid: Option<String>,
+    variant: ProtocolVariant,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+/// Unvalidated Contact Header as read by serde, checked when converted to `ContactHeader`
+struct ContactHeaderRepr {
+    version: u8,
+    flags: ContactHeaderFlags,
+    keepalive: u16,
+    segment_mru: u64,
+    transfer_mru: u64,
+    eid: Option<String>,
+    #[serde(default)]
+    variant: ProtocolVariant,
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<ContactHeaderRepr> for ContactHeader {
+    type Error = TcpclError;
+
+    fn try_from(repr: ContactHeaderRepr) -> Result<ContactHeader, TcpclError> {
+        if let Some(len) = repr.eid.as_ref().map(String::len).filter(|&len| len > MAX_EID_LEN) {
+            return Err(TcpclError::EidTooLong(len));
+        }
+        Ok(ContactHeader {
+            version: repr.version,
+            flags: repr.flags,
+            keepalive: repr.keepalive,
+            segment_mru: repr.segment_mru,
+            transfer_mru: repr.transfer_mru,
+            eid: repr.eid,
+            variant: repr.variant,
+        })
+    }
 }
 
+// bitflags 0.9 expands to the deprecated `try!` macro
+#[allow(deprecated)]
+mod flags {
 bitflags! {
 /// Flags defined for Contact Headers
 pub struct ContactHeaderFlags: u8 {
@@ -46,14 +300,90 @@ pub struct ContactHeaderFlags: u8 {
     const CAN_TLS = 0x01;
 }}
 
+bitflags! {
+/// Flags defined for SESS_TERM
+pub struct SessTermFlags: u8 {
+    /// This SESS_TERM is the reply to a received SESS_TERM
+    const REPLY = 0x01;
+}}
+
+bitflags! {
+/// Flags defined for XFER_SEGMENT
+pub struct XferSegmentFlags: u8 {
+    /// This segment is the last of the transfer
+    const END = 0x01;
+    /// This segment is the first of the transfer
+    const START = 0x02;
+}}
+
+bitflags! {
+/// Flags defined for Extension Items
+pub struct ExtensionFlags: u8 {
+    /// The receiver must understand this Extension Item
+    const CRITICAL = 0x01;
+}}
+}
+pub use flags::{ContactHeaderFlags, CAN_TLS, SessTermFlags, REPLY, XferSegmentFlags, END, START};
+pub use flags::{ExtensionFlags, CRITICAL};
+
 impl ContactHeaderFlags {
     /// Try and parse a octet as a bit flag field
     ///
     /// # Errors
     /// If any flags are set that are not defined in the struct, an Error is returned.
-    pub fn from_bits_strict(bits: u8) -> std::io::Result<ContactHeaderFlags> {
+    pub fn from_bits_strict(bits: u8) -> Result<ContactHeaderFlags, TcpclError> {
         ContactHeaderFlags::from_bits(bits)
-            .ok_or(create_error!("unknown bitflags detected"))
+            .ok_or(TcpclError::UnknownFlags(bits))
+    }
+
+    /// Combine two flag sets, the result contains every flag set in either of them
+    ///
+    /// This is used to add runtime requested flags to a configured default.
+    pub fn merge(&self, other: ContactHeaderFlags) -> ContactHeaderFlags {
+        *self | other
+    }
+
+    /// Restrict two flag sets to the flags set in both of them
+    ///
+    /// This is used during negotiation, where a capability is only used if both peers
+    /// advertise it.
+    pub fn intersect(&self, other: ContactHeaderFlags) -> ContactHeaderFlags {
+        *self & other
+    }
+
+    /// Get the names of the set flags
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.contains(CAN_TLS) {
+            names.push("CAN_TLS");
+        }
+        names
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ContactHeaderFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ContactHeaderFlags {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D)
+                                                -> Result<ContactHeaderFlags, D::Error> {
+        let names: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+        let mut flags = ContactHeaderFlags::empty();
+        for name in names {
+            match name.as_str() {
+                "CAN_TLS" => flags.insert(CAN_TLS),
+                _ => {
+                    return Err(<D::Error as serde::de::Error>::custom(
+                        format!("unknown flag {}", name)))
+                }
+            }
+        }
+        Ok(flags)
     }
 }
 
@@ -68,6 +398,7 @@ impl ContactHeader {
             segment_mru: 0,
             transfer_mru: 0,
             eid: None,
+            variant: ProtocolVariant::default(),
         }
     }
 
@@ -76,15 +407,60 @@ impl ContactHeader {
     /// # Errors
     /// If the eid is to long to be encoded in the Contact Header, an Error is returned.
     /// The size of the eid must fit in a u16.
-    pub fn eid<S: Into<String>>(&mut self, eid: S) -> std::io::Result<&mut ContactHeader> {
+    pub fn eid<S: Into<String>>(&mut self, eid: S) -> Result<&mut ContactHeader, TcpclError> {
         let eid: String = eid.into();
-        if eid.as_bytes().len() > std::u16::MAX as usize {
-            return Err(create_error!("eid to long"));
+        if eid.len() > u16::MAX as usize {
+            return Err(TcpclError::EidTooLong(eid.len()));
         }
         self.eid = Some(eid);
         Ok(self)
     }
 
+    /// Get the version of the Contact Header
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Get the flags of the Contact Header
+    pub fn get_flags(&self) -> ContactHeaderFlags {
+        self.flags
+    }
+
+    /// Get the keepalive of the Contact Header
+    pub fn get_keepalive(&self) -> u16 {
+        self.keepalive
+    }
+
+    /// Get the segment mru of the Contact Header
+    pub fn get_segment_mru(&self) -> u64 {
+        self.segment_mru
+    }
+
+    /// Get the transfer mru of the Contact Header
+    pub fn get_transfer_mru(&self) -> u64 {
+        self.transfer_mru
+    }
+
+    /// Get the eid of the Contact Header
+    pub fn get_eid(&self) -> Option<&str> {
+        self.eid.as_deref()
+    }
+
+    /// Get the eid in a normalized form suitable for comparing eids across peers
+    ///
+    /// A `dtn:` eid in authority form without a path, e.g. `dtn://node`, gets a trailing
+    /// slash appended so it matches `dtn://node/`. All other eids are returned unchanged.
+    pub fn normalized_eid(&self) -> Option<String> {
+        self.eid.as_ref().map(|eid| {
+            match eid.strip_prefix("dtn://") {
+                Some(authority) if !authority.is_empty() && !authority.contains('/') => {
+                    format!("{}/", eid)
+                }
+                _ => eid.clone(),
+            }
+        })
+    }
+
     /// Set flags in the Contact Header
     pub fn flags(&mut self, flags: ContactHeaderFlags) -> &mut ContactHeader {
         self.flags = flags;
@@ -109,6 +485,17 @@ impl ContactHeader {
         self
     }
 
+    /// Set the protocol variant the Contact Header is encoded for
+    ///
+    /// Under `ProtocolVariant::Rfc9174` only the magic, version and flags are serialized and
+    /// parsed, the remaining parameters are left to SESS_INIT. Keepalive, MRUs and eid stay
+    /// set on the header but are silently dropped from the output, so they do not survive a
+    /// roundtrip and have to be carried in a `SessInit` instead.
+    pub fn variant(&mut self, variant: ProtocolVariant) -> &mut ContactHeader {
+        self.variant = variant;
+        self
+    }
+
     /// Set a single flag in the Contact Header
     pub fn set_flag<F>(&mut self, flag: F)
         where F: Into<ContactHeaderFlags> {
@@ -128,136 +515,3580 @@ impl ContactHeader {
 
     /// Serialize the Contact Header to a byte vector
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = Vec::with_capacity(
-            CONTACT_HEADER_BASE_LENGTH +
-                self.eid
-                    .as_ref()
-                    .map_or(0, |eid| eid.as_bytes().len()));
-        buffer.extend(HEADER_MAGIC.iter());
-        buffer.write_u8(self.version).unwrap();
-        buffer.write_u8(self.flags.bits).unwrap();
-        buffer.write_u16::<BigEndian>(self.keepalive).unwrap();
-        buffer.write_u64::<BigEndian>(self.segment_mru).unwrap();
-        buffer.write_u64::<BigEndian>(self.transfer_mru).unwrap();
-        match self.eid.as_ref() {
+        self.serialize_with_eid(self.eid.as_ref())
+    }
+
+    /// Serialize the Contact Header to a byte vector without the eid
+    ///
+    /// The eid length is always encoded as zero, even if an eid is set. This is meant for
+    /// constrained links where the nodes exchange their eids out-of-band. The resulting
+    /// Contact Header always has the fixed length of 26 octets, or 6 octets under RFC 9174.
+    pub fn serialize_compact(&self) -> Vec<u8> {
+        self.serialize_with_eid(None)
+    }
+
+    #[cfg(feature = "smallvec")]
+    /// Serialize the Contact Header to a SmallVec
+    ///
+    /// A Contact Header with a short eid fits into the inline storage of 64 octets and
+    /// needs no heap allocation. Longer eids spill to the heap.
+    pub fn serialize_small(&self) -> SmallVec<[u8; 64]> {
+        let mut buffer = SmallVec::new();
+        self.serialize_into(&mut buffer).expect("Writing to a SmallVec can not fail");
+        buffer
+    }
+
+    /// Serialize the Contact Header directly into a writer
+    ///
+    /// The fields are written one by one, so an unbuffered writer should be wrapped in a
+    /// `BufWriter`.
+    ///
+    /// # Errors
+    /// Errors of the writer are passed through. An eid longer than `MAX_EID_LEN` is reported
+    /// as an `InvalidInput` error wrapping `TcpclError::EidTooLong`.
+    pub fn serialize_into<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_with_eid(w, self.eid.as_ref())
+    }
+
+    fn serialize_with_eid(&self, eid: Option<&String>) -> Vec<u8> {
+        let mut buffer: Vec<u8> = match self.variant {
+            ProtocolVariant::Draft => Vec::with_capacity(
+                CONTACT_HEADER_BASE_LENGTH + 2 + eid.map_or(0, |eid| eid.len())),
+            ProtocolVariant::Rfc9174 => Vec::with_capacity(CONTACT_HEADER_RFC9174_LENGTH),
+        };
+        self.write_with_eid(&mut buffer, eid).expect("The eid length is checked when it is set");
+        buffer
+    }
+
+    fn write_with_eid<W: Write>(&self, w: &mut W, eid: Option<&String>) -> std::io::Result<()> {
+        w.write_all(&HEADER_MAGIC)?;
+        w.write_u8(self.version)?;
+        w.write_u8(self.flags.bits())?;
+        if self.variant == ProtocolVariant::Rfc9174 {
+            return Ok(());
+        }
+        w.write_u16::<BigEndian>(self.keepalive)?;
+        w.write_u64::<BigEndian>(self.segment_mru)?;
+        w.write_u64::<BigEndian>(self.transfer_mru)?;
+        match eid {
             Some(eid) => {
                 let eid_bytes = eid.as_bytes();
-                assert!(eid_bytes.len() <= std::u16::MAX as usize);
-                buffer.write_u16::<BigEndian>(eid_bytes.len() as u16).unwrap();
-                buffer.extend(eid_bytes);
+                if eid_bytes.len() > MAX_EID_LEN {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                                   TcpclError::EidTooLong(eid_bytes.len())));
+                }
+                w.write_u16::<BigEndian>(eid_bytes.len() as u16)?;
+                w.write_all(eid_bytes)?;
             }
-            None => buffer.write_u16::<BigEndian>(0).unwrap(),
+            None => w.write_u16::<BigEndian>(0)?,
         }
+        Ok(())
+    }
 
-        buffer
+    /// Serialize the Contact Header prefixed with its length as a big-endian u32
+    ///
+    /// This framing is not part of tcpcl, it is meant for storing or debugging messages
+    /// where explicit message boundaries are needed.
+    pub fn serialize_framed(&self) -> Vec<u8> {
+        frame(self.serialize())
+    }
+
+    /// Serialize the Contact Header into a buffer that can be written out in several steps
+    pub fn pending_write(&self) -> PendingWrite {
+        PendingWrite::new(self.serialize())
     }
 
     /// Parse the Contact Header from a byte slice
     ///
-    /// # Panics
-    /// If the eid is not valid UTF-8 this function panics.
-    /// This panic will be transformed to an Error in the future.
+    /// If the buffer ends before the Contact Header is complete, `nom::Err::Incomplete` is
+    /// returned.
     ///
     /// # Errors
     /// If the first 4 octets of the buffer do not match the magic pattern an Error is returned.
     /// If the version parsed from the buffer is not supported an Error is returned.
     /// If the flags field contains invalid flags an Error is returned.
+    /// If the eid is not valid UTF-8 an Error is returned.
     /// If any of the reads fails an Error is returned.
-    pub fn deserialize(i: &[u8]) -> IResult<&[u8], ContactHeader> {
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], ContactHeader, TcpclError> {
         contact_header(i)
     }
+
+    /// Read a Contact Header from a reader
+    ///
+    /// Exactly the bytes of the Contact Header are read: first magic, version and flags,
+    /// then the fields up to the eid length, then as many eid octets as the length field
+    /// announces.
+    ///
+    /// # Errors
+    /// If reading fails or the reader ends early an Error is returned.
+    /// The same errors as `deserialize` are returned. A bad magic, version or flags field is
+    /// reported after the first 6 octets, so a peer sending a shorter header of another
+    /// version is rejected without waiting for the remaining fields.
+    pub fn read_from<R: Read>(r: &mut R) -> Result<ContactHeader, TcpclError> {
+        ContactHeader::read_from_with_max_eid(r, MAX_EID_LEN)
+    }
+
+    /// Read a Contact Header from a reader, limiting the length of the eid
+    ///
+    /// The eid length field is checked before the eid is read, so a peer can not make the
+    /// reader allocate more than `max_eid_len` octets of eid.
+    ///
+    /// # Errors
+    /// The same errors as `read_from` are returned. If the eid length field exceeds
+    /// `max_eid_len` the error is `TcpclError::EidTooLong`.
+    pub fn read_from_with_max_eid<R: Read>(r: &mut R, max_eid_len: usize)
+                                           -> Result<ContactHeader, TcpclError> {
+        ContactHeader::read_variant_from(r, ProtocolVariant::Draft, max_eid_len)
+    }
+
+    /// Read a Contact Header using the layout of the given variant from a reader
+    ///
+    /// Under `ProtocolVariant::Rfc9174` exactly 6 octets are read and `max_eid_len` is not
+    /// used.
+    ///
+    /// # Errors
+    /// The same errors as `read_from_with_max_eid` are returned.
+    pub fn read_variant_from<R: Read>(r: &mut R, variant: ProtocolVariant, max_eid_len: usize)
+                                      -> Result<ContactHeader, TcpclError> {
+        let mut buffer = vec![0; CONTACT_HEADER_RFC9174_LENGTH];
+        r.read_exact(&mut buffer)?;
+        if variant == ProtocolVariant::Rfc9174 {
+            let (_, header) = contact_header_rfc9174(&buffer)?;
+            return Ok(header);
+        }
+        header_prefix(&buffer)?;
+        buffer.resize(CONTACT_HEADER_BASE_LENGTH + 2, 0);
+        r.read_exact(&mut buffer[CONTACT_HEADER_RFC9174_LENGTH..])?;
+        let eid_length = u16::from_be_bytes([buffer[CONTACT_HEADER_BASE_LENGTH],
+                                             buffer[CONTACT_HEADER_BASE_LENGTH + 1]]) as usize;
+        if eid_length > max_eid_len {
+            return Err(TcpclError::EidTooLong(eid_length));
+        }
+        buffer.resize(CONTACT_HEADER_BASE_LENGTH + 2 + eid_length, 0);
+        r.read_exact(&mut buffer[CONTACT_HEADER_BASE_LENGTH + 2..])?;
+        ContactHeader::deserialize_exact(&buffer)
+    }
+
+    /// Parse the Contact Header from a byte slice, limiting the length of the eid
+    ///
+    /// The eid length field is checked before waiting for the eid, so a peer can not make
+    /// the reader buffer more than `max_eid_len` octets of eid.
+    ///
+    /// # Errors
+    /// The same errors as `deserialize` are returned. If the eid length field exceeds
+    /// `max_eid_len` the error is `TcpclError::EidTooLong`.
+    pub fn deserialize_with_max_eid(i: &[u8], max_eid_len: usize)
+                                    -> IResult<&[u8], ContactHeader, TcpclError> {
+        contact_header_capped(i, max_eid_len)
+    }
+
+    /// Parse a Contact Header that has to fill the byte slice exactly
+    ///
+    /// # Errors
+    /// If the buffer does not contain a complete Contact Header an Error is returned.
+    /// If the Contact Header is malformed an Error is returned.
+    /// If any bytes remain after the Contact Header an Error with their count is returned.
+    pub fn deserialize_exact(i: &[u8]) -> Result<ContactHeader, TcpclError> {
+        let (rest, header) = contact_header(i)?;
+        if !rest.is_empty() {
+            return Err(TcpclError::TrailingBytes(rest.len()));
+        }
+        Ok(header)
+    }
+
+    /// Parse the Contact Header from a byte slice into an existing Contact Header
+    ///
+    /// The layout is selected by the variant of `out`. The eid allocation of `out` is
+    /// reused if possible. Returns the number of bytes consumed from `i`.
+    ///
+    /// # Errors
+    /// If the buffer does not contain a complete Contact Header an Error is returned.
+    /// If the Contact Header is malformed or the eid is not valid UTF-8 an Error is returned.
+    /// On error `out` is left unchanged.
+    pub fn deserialize_into(i: &[u8], out: &mut ContactHeader) -> Result<usize, TcpclError> {
+        if out.variant == ProtocolVariant::Rfc9174 {
+            let (rest, header) = contact_header_rfc9174(i)?;
+            *out = header;
+            return Ok(i.len() - rest.len());
+        }
+        let (rest, (base, raw_eid)) = contact_header_fields(i, MAX_EID_LEN)?;
+        let (version, flags, keepalive, segment_mru, transfer_mru) = base;
+        let eid = std::str::from_utf8(raw_eid).map_err(|_| TcpclError::InvalidUtf8)?;
+        out.version = version;
+        out.flags = flags;
+        out.keepalive = keepalive;
+        out.segment_mru = segment_mru;
+        out.transfer_mru = transfer_mru;
+        match (eid.is_empty(), out.eid.as_mut()) {
+            (true, _) => out.eid = None,
+            (false, Some(buffer)) => {
+                buffer.clear();
+                buffer.push_str(eid);
+            }
+            (false, None) => out.eid = Some(eid.to_string()),
+        }
+        Ok(i.len() - rest.len())
+    }
+
+    /// Parse a Contact Header written by `serialize_framed`
+    ///
+    /// # Errors
+    /// The same errors as `deserialize` are returned. Additionally an Error is returned if
+    /// the Contact Header does not fill the frame exactly. If the frame ends before the
+    /// number of eid octets given by the eid length field, the error is
+    /// `TcpclError::EidLengthMismatch`.
+    pub fn deserialize_framed(i: &[u8]) -> IResult<&[u8], ContactHeader, TcpclError> {
+        framed_contact_header(i)
+    }
+
+    /// Parse the Contact Header from a byte slice using the layout of the given variant
+    ///
+    /// # Errors
+    /// The same errors as `deserialize` are returned.
+    pub fn deserialize_variant(i: &[u8], variant: ProtocolVariant)
+                               -> IResult<&[u8], ContactHeader, TcpclError> {
+        match variant {
+            ProtocolVariant::Draft => contact_header(i),
+            ProtocolVariant::Rfc9174 => contact_header_rfc9174(i),
+        }
+    }
+
+    fn from_parts(base: HeaderBase, eid: Option<String>) -> ContactHeader {
+        let (version, flags, keepalive, segment_mru, transfer_mru) = base;
+        ContactHeader {
+            version,
+            flags,
+            keepalive,
+            segment_mru,
+            transfer_mru,
+            eid,
+            variant: ProtocolVariant::Draft,
+        }
+    }
+
+    /// Parse the Contact Header from a byte slice and record the time spent in each stage
+    ///
+    /// This is only meant for profiling the parser.
+    #[cfg(feature = "bench-internal")]
+    pub fn deserialize_timed(i: &[u8])
+                             -> IResult<&[u8], (ContactHeader, ParseTiming), TcpclError> {
+        let start = Instant::now();
+        let (i, _) = header_magic(i)?;
+        let magic_done = Instant::now();
+        let (i, base) = header_base(i)?;
+        let base_done = Instant::now();
+        let (i, eid) = map_res(|i| eid_bytes(i, MAX_EID_LEN), eid_from_bytes)(i)?;
+        let eid_done = Instant::now();
+        let timing = ParseTiming {
+            magic: magic_done - start,
+            base_fields: base_done - magic_done,
+            eid: eid_done - base_done,
+        };
+        Ok((i, (ContactHeader::from_parts(base, eid), timing)))
+    }
+
+    /// List the fields in which another Contact Header differs from this one
+    ///
+    /// The values of self are reported as the old and the values of other as the new
+    /// values.
+    pub fn diff(&self, other: &ContactHeader) -> Vec<FieldDiff> {
+        let mut diffs = Vec::new();
+        if self.version != other.version {
+            diffs.push(FieldDiff::Version(self.version, other.version));
+        }
+        if self.flags != other.flags {
+            diffs.push(FieldDiff::Flags(self.flags, other.flags));
+        }
+        if self.keepalive != other.keepalive {
+            diffs.push(FieldDiff::Keepalive(self.keepalive, other.keepalive));
+        }
+        if self.segment_mru != other.segment_mru {
+            diffs.push(FieldDiff::SegmentMru(self.segment_mru, other.segment_mru));
+        }
+        if self.transfer_mru != other.transfer_mru {
+            diffs.push(FieldDiff::TransferMru(self.transfer_mru, other.transfer_mru));
+        }
+        if self.eid != other.eid {
+            diffs.push(FieldDiff::Eid(self.eid.clone(), other.eid.clone()));
+        }
+        if self.variant != other.variant {
+            diffs.push(FieldDiff::Variant(self.variant, other.variant));
+        }
+        diffs
+    }
+
+    /// Compute the session parameters negotiated with a peer's Contact Header
+    ///
+    /// The keepalive interval is the minimum of both advertised intervals. An interval of 0
+    /// disables keepalives, so if either side advertised 0 keepalives are disabled for the
+    /// session. TLS is only used if both sides set CAN_TLS. The MRUs are taken from the peer,
+    /// since they limit what may be sent to it.
+    pub fn negotiate(&self, peer: &ContactHeader) -> NegotiatedParams {
+        NegotiatedParams {
+            keepalive: keepalive_negotiation(self.keepalive, peer.keepalive).interval(),
+            use_tls: self.flags.contains(CAN_TLS) && peer.flags.contains(CAN_TLS),
+            segment_mru: peer.segment_mru,
+            transfer_mru: peer.transfer_mru,
+        }
+    }
+
+    /// Build the local Contact Header sent in response to a peer's Contact Header
+    ///
+    /// All fields are taken from the header in `config`, except CAN_TLS which is only set
+    /// if the local configuration allows TLS and the peer advertised it as well.
+    pub fn respond_to(peer: &ContactHeader, config: &NodeConfig) -> ContactHeader {
+        let mut response = config.header.clone();
+        response.flags.remove(CAN_TLS);
+        if config.allow_tls && peer.flags.contains(CAN_TLS) {
+            response.flags.insert(CAN_TLS);
+        }
+        response
+    }
 }
 
-named!(version< &[u8], u8>, map_opt!(be_u8,
-    |x: u8| -> Option<u8> {
-        match x {
-        0x04 => Some(0x04),
-        _ => None,
-        }}));
-named!(header_flags< &[u8], ContactHeaderFlags>, map_opt!(be_u8,
-    |x: u8| -> Option<ContactHeaderFlags> {
-        ContactHeaderFlags::from_bits(x)
-    }));
-named!(parse_eid< &[u8], Option<String> >,
-    do_parse!(
-        size: be_u16 >>
-        raw_eid: take!(size) >>
-        (match size {
-            0 => None,
-            _ => Some(String::from_utf8(raw_eid.to_vec()).unwrap()),
-        })
-));
-named!(contact_header<ContactHeader>,
-    do_parse!(
-        return_error!(nom::ErrorKind::Custom(257), tag!(HEADER_MAGIC)) >>
-        version: return_error!(nom::ErrorKind::Custom(258), version) >>
-        flags: header_flags >>
-        keepalive: be_u16 >>
-        segment_mru: be_u64 >>
-        transfer_mru: be_u64 >>
-        eid: parse_eid >>
-        (ContactHeader {
-        version: version,
-        flags: flags,
-        keepalive: keepalive,
-        segment_mru: segment_mru,
-        transfer_mru: transfer_mru,
-        eid: eid })
-));
+impl Default for ContactHeader {
+    fn default() -> ContactHeader {
+        ContactHeader::new()
+    }
+}
 
+impl std::fmt::Display for ContactHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "TCPCLv{} ContactHeader(", self.version)?;
+        match self.eid {
+            Some(ref eid) => write!(f, "eid={:?}, ", eid)?,
+            None => write!(f, "eid=<none>, ")?,
+        }
+        write!(f, "keepalive={}s, segment_mru={}, transfer_mru={}, flags=[{}])",
+               self.keepalive, self.segment_mru, self.transfer_mru,
+               self.flags.names().join(", "))
+    }
+}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A field that differs between two Contact Headers, with the old and the new value
+pub enum FieldDiff {
+    /// The version differs
+    Version(u8, u8),
+    /// The flags differ
+    Flags(ContactHeaderFlags, ContactHeaderFlags),
+    /// The keepalive interval differs
+    Keepalive(u16, u16),
+    /// The segment mru differs
+    SegmentMru(u64, u64),
+    /// The transfer mru differs
+    TransferMru(u64, u64),
+    /// The eid differs
+    Eid(Option<String>, Option<String>),
+    /// The protocol variant differs
+    Variant(ProtocolVariant, ProtocolVariant),
+}
 
-    #[test]
-    /// Test simple creation of a default header
-    fn test_init_contact_header() {
-        let contact_header = ContactHeader::new();
-        assert_eq!(contact_header.eid, None);
-        assert_eq!(contact_header.version, 4);
-        assert_eq!(contact_header.flags, super::ContactHeaderFlags::empty());
-        assert_eq!(contact_header.transfer_mru, 0);
-        assert_eq!(contact_header.segment_mru, 0);
+#[derive(Debug)]
+/// Streaming decoder for Contact Headers arriving over several reads
+///
+/// Incoming bytes are buffered until a complete Contact Header is available. Bytes
+/// following the Contact Header stay buffered.
+pub struct ContactHeaderDecoder {
+    buffer: Vec<u8>,
+    max_eid_len: usize,
+    variant: ProtocolVariant,
+}
+
+impl ContactHeaderDecoder {
+    /// Create a new decoder with an empty buffer
+    pub fn new() -> ContactHeaderDecoder {
+        ContactHeaderDecoder {
+            buffer: Vec::new(),
+            max_eid_len: MAX_EID_LEN,
+            variant: ProtocolVariant::default(),
+        }
     }
 
-    #[test]
-    /// Test setting and unsetting a flag
-    fn test_set_unset_flag() {
-        let mut contact_header = ContactHeader::new();
-        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
-        contact_header.set_flag(CAN_TLS);
-        assert_eq!(contact_header.flags.bits(), 0x01);
-        assert_eq!(contact_header.flags, CAN_TLS);
-        contact_header.unset_flag(CAN_TLS);
-        assert_eq!(contact_header.flags.bits(), 0x00);
-        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+    /// Set the maximum eid length accepted from the peer
+    pub fn max_eid_len(&mut self, max_eid_len: usize) -> &mut ContactHeaderDecoder {
+        self.max_eid_len = max_eid_len;
+        self
     }
 
-    #[test]
-    /// Test clearing the bitfield
-    fn test_set_clear_flag() {
-        let mut contact_header = ContactHeader::new();
-        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
-        contact_header.set_flag(CAN_TLS);
-        assert_eq!(contact_header.flags, CAN_TLS);
-        contact_header.clear_flags();
-        assert_eq!(contact_header.flags.bits(), 0x00);
-        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+    /// Set the protocol variant whose layout is expected from the peer
+    pub fn variant(&mut self, variant: ProtocolVariant) -> &mut ContactHeaderDecoder {
+        self.variant = variant;
+        self
     }
 
-    #[test]
-    /// Test setting the same flags twice
+    /// Append received bytes to the buffer
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Try and parse a Contact Header from the buffered bytes
     ///
-    /// This should behave the same way as setting it only once.
-    fn test_duplicate_set() {
-        let mut contact_header = ContactHeader::new();
-        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
-        contact_header.set_flag(CAN_TLS);
-        assert_eq!(contact_header.flags, CAN_TLS);
-        contact_header.set_flag(CAN_TLS);
+    /// Returns `None` if more bytes are needed. On success the bytes of the Contact Header
+    /// are removed from the buffer.
+    ///
+    /// # Errors
+    /// If the buffered bytes are not a valid Contact Header an Error is returned and the
+    /// buffer is left unchanged.
+    pub fn try_parse(&mut self) -> Option<Result<ContactHeader, TcpclError>> {
+        let parsed = match self.variant {
+            ProtocolVariant::Draft => contact_header_capped(&self.buffer, self.max_eid_len),
+            ProtocolVariant::Rfc9174 => contact_header_rfc9174(&self.buffer),
+        };
+        let (consumed, header) = match parsed {
+            Ok((rest, header)) => (self.buffer.len() - rest.len(), header),
+            Err(nom::Err::Incomplete(_)) => return None,
+            Err(e) => return Some(Err(e.into())),
+        };
+        self.buffer.drain(..consumed);
+        Some(Ok(header))
+    }
+
+    /// Get the bytes that have been buffered but not consumed yet
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Default for ContactHeaderDecoder {
+    fn default() -> ContactHeaderDecoder {
+        ContactHeaderDecoder::new()
+    }
+}
+
+/// Find the offset of the next Contact Header magic in a buffer
+///
+/// This can be used to skip corrupted bytes in a stream. It only works for Contact
+/// Headers, since the magic is the only self-delimiting marker in the protocol. Messages
+/// after the Contact Header can not be resynchronized this way.
+pub fn resync(buf: &[u8]) -> Option<usize> {
+    buf.windows(HEADER_MAGIC.len()).position(|window| window == HEADER_MAGIC)
+}
+
+/// Parse a Contact Header from the start of a memory-mapped file
+///
+/// This is meant for offline tools reading captures through a memory map. The returned
+/// Contact Header owns its eid, so nothing borrowed from `slice` outlives it and the
+/// mapping can be dropped right after parsing. The number of octets consumed is returned
+/// alongside the Contact Header, so the caller can continue with the following data.
+///
+/// # Errors
+/// If the slice ends before the Contact Header is complete, `TcpclError::Incomplete` is
+/// returned. Otherwise the same errors as `ContactHeader::deserialize` are returned.
+pub fn parse_mmap(slice: &[u8]) -> Result<(ContactHeader, usize), TcpclError> {
+    let (rest, header) = contact_header(slice)?;
+    Ok((header, slice.len() - rest.len()))
+}
+
+/// Send the local Contact Header and receive the Contact Header of the peer
+///
+/// The local Contact Header is written in a single write before the peer's Contact Header
+/// is read with `ContactHeader::read_variant_from`, using the variant of `local`. Since the
+/// peer is usually not trusted, `max_eid_len` should be set well below `MAX_EID_LEN`.
+///
+/// # Errors
+/// If writing or reading fails an Error is returned.
+/// If the peer's Contact Header is malformed an Error is returned. If the peer uses a
+/// version other than 4 the error is `TcpclError::UnsupportedVersion`. If the peer's eid
+/// is longer than `max_eid_len` the error is `TcpclError::EidTooLong`.
+pub fn perform_contact_exchange<S: Read + Write>(stream: &mut S, local: &ContactHeader,
+                                                 max_eid_len: usize)
+                                                 -> Result<ContactHeader, TcpclError> {
+    stream.write_all(&local.serialize())?;
+    stream.flush()?;
+    ContactHeader::read_variant_from(stream, local.variant, max_eid_len)
+}
+
+/// Stream with an adjustable read timeout, as used by `DeadlineStream`
+pub trait ReadTimeout: Read + Write {
+    /// Get the current read timeout, `None` means reads block indefinitely
+    fn read_timeout(&self) -> std::io::Result<Option<Duration>>;
+
+    /// Set the read timeout, `None` means reads block indefinitely
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn read_timeout(&self) -> std::io::Result<Option<Duration>> {
+        TcpStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[derive(Debug)]
+/// Stream wrapper that fails all reads after a deadline
+///
+/// This is meant to bound the time a peer may take to send its Contact Header, separate
+/// from the read timeout used for the rest of the session. The read timeout of the stream
+/// is restored when the wrapper is dropped.
+pub struct DeadlineStream<'a, S: ReadTimeout + 'a> {
+    stream: &'a mut S,
+    deadline: Instant,
+    previous_timeout: Option<Duration>,
+}
+
+impl<'a, S: ReadTimeout> DeadlineStream<'a, S> {
+    /// Wrap a stream so reads fail once `timeout` has passed
+    ///
+    /// # Errors
+    /// If the read timeout of the stream can not be queried an Error is returned.
+    pub fn new(stream: &'a mut S, timeout: Duration) -> std::io::Result<DeadlineStream<'a, S>> {
+        let previous_timeout = stream.read_timeout()?;
+        Ok(DeadlineStream {
+            stream,
+            deadline: Instant::now() + timeout,
+            previous_timeout,
+        })
+    }
+}
+
+impl<'a, S: ReadTimeout> Read for DeadlineStream<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(Error::new(ErrorKind::TimedOut, "contact header deadline passed"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        match self.stream.read(buf) {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                Err(Error::new(ErrorKind::TimedOut, "contact header deadline passed"))
+            }
+            result => result,
+        }
+    }
+}
+
+impl<'a, S: ReadTimeout> Write for DeadlineStream<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<'a, S: ReadTimeout> Drop for DeadlineStream<'a, S> {
+    fn drop(&mut self) {
+        let _ = self.stream.set_read_timeout(self.previous_timeout);
+    }
+}
+
+
+#[cfg(feature = "bench-internal")]
+#[derive(Debug, Clone, Copy, Default)]
+/// Time spent in each stage of parsing a Contact Header
+pub struct ParseTiming {
+    magic: Duration,
+    base_fields: Duration,
+    eid: Duration,
+}
+
+#[cfg(feature = "bench-internal")]
+impl ParseTiming {
+    /// Get the time spent matching the magic bytes
+    pub fn magic(&self) -> Duration {
+        self.magic
+    }
+
+    /// Get the time spent parsing the fixed size fields
+    pub fn base_fields(&self) -> Duration {
+        self.base_fields
+    }
+
+    /// Get the time spent parsing the eid
+    pub fn eid(&self) -> Duration {
+        self.eid
+    }
+
+    /// Get the time spent in all stages
+    pub fn total(&self) -> Duration {
+        self.magic + self.base_fields + self.eid
+    }
+}
+
+
+#[derive(Debug)]
+/// Local node configuration used when answering a peer's Contact Header
+pub struct NodeConfig {
+    header: ContactHeader,
+    allow_tls: bool,
+}
+
+impl NodeConfig {
+    /// Create a new node configuration using `header` as the template for responses
+    ///
+    /// TLS is not allowed by default.
+    pub fn new(header: ContactHeader) -> NodeConfig {
+        NodeConfig {
+            header,
+            allow_tls: false,
+        }
+    }
+
+    /// Set whether this node may advertise CAN_TLS
+    pub fn allow_tls(&mut self, allow_tls: bool) -> &mut NodeConfig {
+        self.allow_tls = allow_tls;
+        self
+    }
+}
+
+fn version(i: &[u8]) -> IResult<&[u8], u8, TcpclError> {
+    let (rest, version) = be_u8(i)?;
+    match version {
+        0x04 => Ok((rest, version)),
+        _ => Err(nom::Err::Error(TcpclError::UnsupportedVersion(version))),
+    }
+}
+fn header_flags(i: &[u8]) -> IResult<&[u8], ContactHeaderFlags, TcpclError> {
+    let (rest, bits) = be_u8(i)?;
+    match ContactHeaderFlags::from_bits_strict(bits) {
+        Ok(flags) => Ok((rest, flags)),
+        Err(e) => Err(nom::Err::Error(e)),
+    }
+}
+/// Parse the eid length field and the raw eid, failing with `TcpclError::EidTooLong` if the
+/// length exceeds `max_len`
+fn eid_bytes(i: &[u8], max_len: usize) -> IResult<&[u8], &[u8], TcpclError> {
+    let (i, length) = be_u16(i)?;
+    if length as usize > max_len {
+        return Err(nom::Err::Error(TcpclError::EidTooLong(length as usize)));
+    }
+    take(length)(i)
+}
+fn eid_from_bytes(raw_eid: &[u8]) -> Result<Option<String>, std::string::FromUtf8Error> {
+    match raw_eid.len() {
+        0 => Ok(None),
+        _ => String::from_utf8(raw_eid.to_vec()).map(Some),
+    }
+}
+fn header_magic(i: &[u8]) -> IResult<&[u8], &[u8], TcpclError> {
+    tag(HEADER_MAGIC)(i).map_err(|e: nom::Err<TcpclError>| e.map(|_| TcpclError::BadMagic))
+}
+/// Version, flags, keepalive, segment mru and transfer mru of a Contact Header
+type HeaderBase = (u8, ContactHeaderFlags, u16, u64, u64);
+/// Fields of a Contact Header with the raw eid
+type HeaderFields<'a> = (HeaderBase, &'a [u8]);
+
+fn header_base(i: &[u8]) -> IResult<&[u8], HeaderBase, TcpclError> {
+    let (i, version) = version(i)?;
+    let (i, flags) = header_flags(i)?;
+    let (i, keepalive) = be_u16(i)?;
+    let (i, segment_mru) = be_u64(i)?;
+    let (i, transfer_mru) = be_u64(i)?;
+    Ok((i, (version, flags, keepalive, segment_mru, transfer_mru)))
+}
+fn contact_header_fields<'a>(i: &'a [u8], max_eid_len: usize)
+                             -> IResult<&'a [u8], HeaderFields<'a>, TcpclError> {
+    let (i, _) = header_magic(i)?;
+    let (i, base) = header_base(i)?;
+    let (i, raw_eid) = eid_bytes(i, max_eid_len)?;
+    Ok((i, (base, raw_eid)))
+}
+fn contact_header_capped(i: &[u8], max_eid_len: usize)
+                         -> IResult<&[u8], ContactHeader, TcpclError> {
+    map_res(|i| contact_header_fields(i, max_eid_len),
+            |(base, raw_eid)| eid_from_bytes(raw_eid)
+                .map(|eid| ContactHeader::from_parts(base, eid)))(i)
+}
+fn contact_header(i: &[u8]) -> IResult<&[u8], ContactHeader, TcpclError> {
+    contact_header_capped(i, MAX_EID_LEN)
+}
+/// Parse a Contact Header that has to fill a length-delimited frame exactly
+///
+/// Since the frame is known to be complete, a Contact Header that is cut short by the end
+/// of the frame is an error instead of Incomplete.
+fn framed_contact_header(i: &[u8]) -> IResult<&[u8], ContactHeader, TcpclError> {
+    let (rest, frame) = length_data(be_u32)(i)?;
+    match contact_header(frame) {
+        Ok((remaining, header)) => {
+            if remaining.is_empty() {
+                Ok((rest, header))
+            } else {
+                Err(nom::Err::Error(TcpclError::TrailingBytes(remaining.len())))
+            }
+        }
+        Err(nom::Err::Incomplete(_)) => {
+            if frame.len() >= CONTACT_HEADER_BASE_LENGTH + 2 {
+                Err(nom::Err::Error(TcpclError::EidLengthMismatch))
+            } else {
+                Err(nom::Err::Error(TcpclError::Incomplete))
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+/// Magic, version and flags, the part of the Contact Header shared by all variants
+fn header_prefix(i: &[u8]) -> IResult<&[u8], (u8, ContactHeaderFlags), TcpclError> {
+    let (i, _) = header_magic(i)?;
+    let (i, version) = version(i)?;
+    let (i, flags) = header_flags(i)?;
+    Ok((i, (version, flags)))
+}
+fn contact_header_rfc9174(i: &[u8]) -> IResult<&[u8], ContactHeader, TcpclError> {
+    let (i, (version, flags)) = header_prefix(i)?;
+    Ok((i, ContactHeader {
+        version,
+        flags,
+        keepalive: 0,
+        segment_mru: 0,
+        transfer_mru: 0,
+        eid: None,
+        variant: ProtocolVariant::Rfc9174 }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Extension Item carried by SESS_INIT and the first XFER_SEGMENT of a transfer
+pub struct ExtensionItem {
+    flags: ExtensionFlags,
+    item_type: u16,
+    value: Vec<u8>,
+}
+
+impl ExtensionItem {
+    /// Create a new Extension Item
+    ///
+    /// # Errors
+    /// If the value is to long to be encoded in the Extension Item, an Error is returned.
+    /// The size of the value must fit in a u16.
+    pub fn new(flags: ExtensionFlags, item_type: u16, value: Vec<u8>)
+               -> Result<ExtensionItem, TcpclError> {
+        if value.len() > u16::MAX as usize {
+            return Err(TcpclError::ExtensionValueTooLong(value.len()));
+        }
+        Ok(ExtensionItem {
+            flags,
+            item_type,
+            value,
+        })
+    }
+
+    /// Get the flags of the Extension Item
+    pub fn flags(&self) -> ExtensionFlags {
+        self.flags
+    }
+
+    /// Check if the Extension Item is critical
+    ///
+    /// A critical Extension Item of an unknown type should cause the session or transfer
+    /// to be refused.
+    pub fn is_critical(&self) -> bool {
+        self.flags.contains(CRITICAL)
+    }
+
+    /// Get the type of the Extension Item
+    pub fn item_type(&self) -> u16 {
+        self.item_type
+    }
+
+    /// Get the value of the Extension Item
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    fn serialized_length(&self) -> usize {
+        5 + self.value.len()
+    }
+
+    fn serialize_into(&self, buffer: &mut Vec<u8>) {
+        buffer.write_u8(self.flags.bits()).unwrap();
+        buffer.write_u16::<BigEndian>(self.item_type).unwrap();
+        buffer.write_u16::<BigEndian>(self.value.len() as u16).unwrap();
+        buffer.extend(&self.value);
+    }
+
+    /// Serialize a list of Extension Items prefixed with their total length
+    ///
+    /// # Errors
+    /// If the total length exceeds `MAX_EXTENSIONS_LEN` an Error is returned.
+    pub fn serialize_list(items: &[ExtensionItem]) -> Result<Vec<u8>, TcpclError> {
+        let mut buffer = Vec::new();
+        serialize_extension_items(items, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Parse a list of Extension Items prefixed with their total length
+    ///
+    /// Flags not defined in ExtensionFlags are ignored.
+    ///
+    /// # Errors
+    /// If the total length exceeds `MAX_EXTENSIONS_LEN` an Error is returned before any
+    /// item is read.
+    pub fn deserialize_list(i: &[u8]) -> IResult<&[u8], Vec<ExtensionItem>, TcpclError> {
+        extension_items(i)
+    }
+}
+
+/// Check that Extension Items fit into `MAX_EXTENSIONS_LEN` and return their total length
+fn extensions_length<'a, I>(items: I) -> Result<u32, TcpclError>
+    where I: IntoIterator<Item = &'a ExtensionItem> {
+    let length: usize = items.into_iter().map(|item| item.serialized_length()).sum();
+    if length > MAX_EXTENSIONS_LEN as usize {
+        return Err(TcpclError::ExtensionsTooLong(length));
+    }
+    Ok(length as u32)
+}
+
+/// Serialize a list of Extension Items prefixed with their total length
+fn serialize_extension_items(items: &[ExtensionItem], buffer: &mut Vec<u8>)
+                             -> Result<(), TcpclError> {
+    buffer.write_u32::<BigEndian>(extensions_length(items)?).unwrap();
+    for item in items {
+        item.serialize_into(buffer);
+    }
+    Ok(())
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// SESS_INIT message
+pub struct SessInit {
+    keepalive_interval: u16,
+    segment_mru: u64,
+    transfer_mru: u64,
+    node_id: String,
+    extensions: Vec<ExtensionItem>,
+}
+
+impl SessInit {
+    /// Create a new SESS_INIT
+    pub fn new() -> SessInit {
+        SessInit {
+            keepalive_interval: 0,
+            segment_mru: 0,
+            transfer_mru: 0,
+            node_id: String::new(),
+            extensions: Vec::new(),
+        }
+    }
+
+    /// Set the keepalive interval in the SESS_INIT
+    pub fn keepalive_interval(&mut self, keepalive_interval: u16) -> &mut SessInit {
+        self.keepalive_interval = keepalive_interval;
+        self
+    }
+
+    /// Set segment mru in the SESS_INIT
+    pub fn segment_mru(&mut self, segment_mru: u64) -> &mut SessInit {
+        self.segment_mru = segment_mru;
+        self
+    }
+
+    /// Set transfer mru in the SESS_INIT
+    pub fn transfer_mru(&mut self, transfer_mru: u64) -> &mut SessInit {
+        self.transfer_mru = transfer_mru;
+        self
+    }
+
+    /// Set the node id in the SESS_INIT
+    ///
+    /// # Errors
+    /// If the node id is to long to be encoded in the SESS_INIT, an Error is returned.
+    /// The size of the node id must fit in a u16.
+    pub fn node_id<S: Into<String>>(&mut self, node_id: S) -> Result<&mut SessInit, TcpclError> {
+        let node_id: String = node_id.into();
+        if node_id.len() > u16::MAX as usize {
+            return Err(TcpclError::NodeIdTooLong(node_id.len()));
+        }
+        self.node_id = node_id;
+        Ok(self)
+    }
+
+    /// Append a session Extension Item to the SESS_INIT
+    ///
+    /// # Errors
+    /// If the Extension Items would exceed `MAX_EXTENSIONS_LEN` in total an Error is
+    /// returned.
+    pub fn add_extension(&mut self, extension: ExtensionItem)
+                         -> Result<&mut SessInit, TcpclError> {
+        extensions_length(self.extensions.iter().chain(Some(&extension)))?;
+        self.extensions.push(extension);
+        Ok(self)
+    }
+
+    /// Get the keepalive interval of the SESS_INIT
+    pub fn get_keepalive_interval(&self) -> u16 {
+        self.keepalive_interval
+    }
+
+    /// Get the segment mru of the SESS_INIT
+    pub fn get_segment_mru(&self) -> u64 {
+        self.segment_mru
+    }
+
+    /// Get the transfer mru of the SESS_INIT
+    pub fn get_transfer_mru(&self) -> u64 {
+        self.transfer_mru
+    }
+
+    /// Get the node id of the SESS_INIT
+    pub fn get_node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Get the session Extension Items of the SESS_INIT
+    pub fn get_extensions(&self) -> &[ExtensionItem] {
+        &self.extensions
+    }
+
+    /// Get all session Extension Items of the given type in the order they were received
+    pub fn extensions_of_type(&self, type_code: u16) -> Vec<&ExtensionItem> {
+        self.extensions
+            .iter()
+            .filter(|item| item.item_type == type_code)
+            .collect()
+    }
+
+    /// Serialize the SESS_INIT to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(
+            25 + self.node_id.len() +
+                self.extensions.iter().map(|item| item.serialized_length()).sum::<usize>());
+        buffer.write_u8(MSG_TYPE_SESS_INIT).unwrap();
+        buffer.write_u16::<BigEndian>(self.keepalive_interval).unwrap();
+        buffer.write_u64::<BigEndian>(self.segment_mru).unwrap();
+        buffer.write_u64::<BigEndian>(self.transfer_mru).unwrap();
+        buffer.write_u16::<BigEndian>(self.node_id.len() as u16).unwrap();
+        buffer.extend(self.node_id.as_bytes());
+        serialize_extension_items(&self.extensions, &mut buffer)
+            .expect("The extension length is checked when an extension is added");
+        buffer
+    }
+
+    /// Parse the SESS_INIT from a byte slice
+    ///
+    /// # Errors
+    /// If the message type is not SESS_INIT an Error is returned.
+    /// If the node id is not valid UTF-8 an Error is returned.
+    /// If the Extension Items do not fill their length field exactly an Error is returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], SessInit, TcpclError> {
+        sess_init(i)
+    }
+}
+
+impl Default for SessInit {
+    fn default() -> SessInit {
+        SessInit::new()
+    }
+}
+
+fn extension_item(i: &[u8]) -> IResult<&[u8], ExtensionItem, TcpclError> {
+    let (i, flags) = map(be_u8, ExtensionFlags::from_bits_truncate)(i)?;
+    let (i, item_type) = be_u16(i)?;
+    let (i, value) = length_data(be_u16)(i)?;
+    Ok((i, ExtensionItem {
+        flags,
+        item_type,
+        value: value.to_vec() }))
+}
+fn extension_items(i: &[u8]) -> IResult<&[u8], Vec<ExtensionItem>, TcpclError> {
+    let (_, length) = be_u32(i)?;
+    if length > MAX_EXTENSIONS_LEN {
+        return Err(nom::Err::Error(TcpclError::ExtensionsTooLong(length as usize)));
+    }
+    map_parser(length_data(be_u32), all_consuming(many0(complete(extension_item))))(i)
+}
+fn sess_init(i: &[u8]) -> IResult<&[u8], SessInit, TcpclError> {
+    let (i, _) = tag([MSG_TYPE_SESS_INIT])(i)?;
+    let (i, keepalive_interval) = be_u16(i)?;
+    let (i, segment_mru) = be_u64(i)?;
+    let (i, transfer_mru) = be_u64(i)?;
+    let (i, node_id) = map_res(length_data(be_u16),
+                               |raw: &[u8]| String::from_utf8(raw.to_vec()))(i)?;
+    let (i, extensions) = extension_items(i)?;
+    Ok((i, SessInit {
+        keepalive_interval,
+        segment_mru,
+        transfer_mru,
+        node_id,
+        extensions }))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// KEEPALIVE message
+///
+/// A KEEPALIVE consists of the message type only and carries no payload.
+pub struct Keepalive;
+
+impl Keepalive {
+    /// Serialize the KEEPALIVE to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        vec![MSG_TYPE_KEEPALIVE]
+    }
+
+    /// Parse the KEEPALIVE from a byte slice
+    ///
+    /// Only the message type octet is consumed, any following bytes are left untouched.
+    ///
+    /// # Errors
+    /// If the message type is not KEEPALIVE an Error is returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], Keepalive, TcpclError> {
+        keepalive(i)
+    }
+}
+
+fn keepalive(i: &[u8]) -> IResult<&[u8], Keepalive, TcpclError> {
+    value(Keepalive, tag([MSG_TYPE_KEEPALIVE]))(i)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reason for terminating a session
+pub enum SessTermReason {
+    /// Reason unknown or not specified
+    Unknown = 0x00,
+    /// The session is closed due to idleness
+    IdleTimeout = 0x01,
+    /// The node cannot conform to the protocol version of the peer
+    VersionMismatch = 0x02,
+    /// The node is too busy to handle the session
+    Busy = 0x03,
+    /// The node cannot interpret or negotiate the Contact Header or SESS_INIT
+    ContactFailure = 0x04,
+    /// The node has run into a resource limit
+    ResourceExhaustion = 0x05,
+}
+
+impl SessTermReason {
+    /// Try and map a reason code octet to a reason
+    pub fn from_u8(code: u8) -> Option<SessTermReason> {
+        match code {
+            0x00 => Some(SessTermReason::Unknown),
+            0x01 => Some(SessTermReason::IdleTimeout),
+            0x02 => Some(SessTermReason::VersionMismatch),
+            0x03 => Some(SessTermReason::Busy),
+            0x04 => Some(SessTermReason::ContactFailure),
+            0x05 => Some(SessTermReason::ResourceExhaustion),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// SESS_TERM message
+pub struct SessTerm {
+    flags: SessTermFlags,
+    reserved_flags: u8,
+    reason: SessTermReason,
+}
+
+impl SessTerm {
+    /// Create a new SESS_TERM
+    pub fn new(flags: SessTermFlags, reason: SessTermReason) -> SessTerm {
+        SessTerm {
+            flags,
+            reserved_flags: 0,
+            reason,
+        }
+    }
+
+    /// Get the flags of the SESS_TERM
+    pub fn flags(&self) -> SessTermFlags {
+        self.flags
+    }
+
+    /// Get the reserved bits that were set in the flags of a received SESS_TERM
+    ///
+    /// Reserved bits are never serialized.
+    pub fn reserved_flags(&self) -> u8 {
+        self.reserved_flags
+    }
+
+    /// Get the reason of the SESS_TERM
+    pub fn reason(&self) -> SessTermReason {
+        self.reason
+    }
+
+    /// Serialize the SESS_TERM to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        vec![MSG_TYPE_SESS_TERM, self.flags.bits(), self.reason as u8]
+    }
+
+    /// Parse the SESS_TERM from a byte slice
+    ///
+    /// # Errors
+    /// If the message type is not SESS_TERM an Error is returned.
+    /// If the reason code is unknown an Error is returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], SessTerm, TcpclError> {
+        sess_term(i)
+    }
+}
+
+fn sess_term(i: &[u8]) -> IResult<&[u8], SessTerm, TcpclError> {
+    let (i, _) = tag([MSG_TYPE_SESS_TERM])(i)?;
+    let (i, bits) = be_u8(i)?;
+    let (i, reason) = map_opt(be_u8, SessTermReason::from_u8)(i)?;
+    Ok((i, SessTerm {
+        flags: SessTermFlags::from_bits_truncate(bits),
+        reserved_flags: bits & !SessTermFlags::all().bits(),
+        reason }))
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// XFER_SEGMENT message
+pub struct XferSegment {
+    flags: XferSegmentFlags,
+    reserved_flags: u8,
+    transfer_id: u64,
+    extensions: Vec<ExtensionItem>,
+    data: Vec<u8>,
+}
+
+impl XferSegment {
+    /// Create a new XFER_SEGMENT without transfer Extension Items
+    pub fn new(flags: XferSegmentFlags, transfer_id: u64, data: Vec<u8>) -> XferSegment {
+        XferSegment {
+            flags,
+            reserved_flags: 0,
+            transfer_id,
+            extensions: Vec::new(),
+            data,
+        }
+    }
+
+    /// Append a transfer Extension Item to the XFER_SEGMENT
+    ///
+    /// # Errors
+    /// Transfer Extension Items are only carried by the first segment of a transfer. If the
+    /// START flag is not set an Error is returned.
+    /// If the Extension Items would exceed `MAX_EXTENSIONS_LEN` in total an Error is
+    /// returned.
+    pub fn add_extension(&mut self, extension: ExtensionItem)
+                         -> Result<&mut XferSegment, TcpclError> {
+        if !self.flags.contains(START) {
+            return Err(TcpclError::ExtensionsWithoutStart(self.transfer_id));
+        }
+        extensions_length(self.extensions.iter().chain(Some(&extension)))?;
+        self.extensions.push(extension);
+        Ok(self)
+    }
+
+    /// Get the flags of the XFER_SEGMENT
+    pub fn flags(&self) -> XferSegmentFlags {
+        self.flags
+    }
+
+    /// Get the reserved bits that were set in the flags of a received XFER_SEGMENT
+    ///
+    /// Reserved bits are never serialized.
+    pub fn reserved_flags(&self) -> u8 {
+        self.reserved_flags
+    }
+
+    /// Get the transfer id of the XFER_SEGMENT
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    /// Get the transfer Extension Items of the XFER_SEGMENT
+    pub fn extensions(&self) -> &[ExtensionItem] {
+        &self.extensions
+    }
+
+    /// Get the data carried by the XFER_SEGMENT
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Serialize the XFER_SEGMENT to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(18 + self.data.len());
+        buffer.write_u8(MSG_TYPE_XFER_SEGMENT).unwrap();
+        buffer.write_u8(self.flags.bits()).unwrap();
+        buffer.write_u64::<BigEndian>(self.transfer_id).unwrap();
+        if self.flags.contains(START) {
+            serialize_extension_items(&self.extensions, &mut buffer)
+                .expect("The extension length is checked when an extension is added");
+        }
+        buffer.write_u64::<BigEndian>(self.data.len() as u64).unwrap();
+        buffer.extend(&self.data);
+        buffer
+    }
+
+    /// Parse the XFER_SEGMENT from a byte slice
+    ///
+    /// Reserved flags are ignored, they are available from `reserved_flags`.
+    ///
+    /// # Errors
+    /// If the message type is not XFER_SEGMENT an Error is returned.
+    /// If the Extension Items do not fill their length field exactly an Error is returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], XferSegment, TcpclError> {
+        xfer_segment(i)
+    }
+
+    /// Parse the XFER_SEGMENT from a byte slice, limiting the length of the data
+    ///
+    /// `deserialize` limits the data to `MAX_SEGMENT_LEN`. Usually `max_data_len` is the
+    /// local segment mru.
+    ///
+    /// # Errors
+    /// The same errors as `deserialize` are returned. If the data length field exceeds
+    /// `max_data_len` the error is `TcpclError::SegmentTooLong`.
+    pub fn deserialize_with_max(i: &[u8], max_data_len: u64)
+                                -> IResult<&[u8], XferSegment, TcpclError> {
+        map(|i| xfer_segment_ref(i, max_data_len), XferSegmentRef::into_owned)(i)
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// XFER_SEGMENT message borrowing its data from the parsed buffer
+pub struct XferSegmentRef<'a> {
+    flags: XferSegmentFlags,
+    reserved_flags: u8,
+    transfer_id: u64,
+    extensions: Vec<ExtensionItem>,
+    data: &'a [u8],
+}
+
+impl<'a> XferSegmentRef<'a> {
+    /// Get the flags of the XFER_SEGMENT
+    pub fn flags(&self) -> XferSegmentFlags {
+        self.flags
+    }
+
+    /// Get the reserved bits that were set in the flags of the XFER_SEGMENT
+    pub fn reserved_flags(&self) -> u8 {
+        self.reserved_flags
+    }
+
+    /// Get the transfer id of the XFER_SEGMENT
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    /// Get the transfer Extension Items of the XFER_SEGMENT
+    pub fn extensions(&self) -> &[ExtensionItem] {
+        &self.extensions
+    }
+
+    /// Get the data carried by the XFER_SEGMENT
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Copy the data to create an owned XFER_SEGMENT
+    pub fn into_owned(self) -> XferSegment {
+        XferSegment {
+            flags: self.flags,
+            reserved_flags: self.reserved_flags,
+            transfer_id: self.transfer_id,
+            extensions: self.extensions,
+            data: self.data.to_vec(),
+        }
+    }
+}
+
+/// Parse a XFER_SEGMENT from a byte slice without copying its data
+///
+/// The data of the returned segment points into `i`. The transfer Extension Items are
+/// still copied, since they are small.
+///
+/// # Errors
+/// The same errors as `XferSegment::deserialize` are returned.
+pub fn parse_segment_borrowed<'a>(i: &'a [u8])
+                                  -> IResult<&'a [u8], XferSegmentRef<'a>, TcpclError> {
+    xfer_segment_ref(i, MAX_SEGMENT_LEN)
+}
+
+fn xfer_segment_ref<'a>(i: &'a [u8], max_data_len: u64)
+                        -> IResult<&'a [u8], XferSegmentRef<'a>, TcpclError> {
+    let (i, _) = tag([MSG_TYPE_XFER_SEGMENT])(i)?;
+    let (i, bits) = be_u8(i)?;
+    let flags = XferSegmentFlags::from_bits_truncate(bits);
+    let (i, transfer_id) = be_u64(i)?;
+    let (i, extensions) = cond(flags.contains(START), extension_items)(i)?;
+    let (i, length) = be_u64(i)?;
+    if length > max_data_len {
+        return Err(nom::Err::Error(TcpclError::SegmentTooLong(length)));
+    }
+    let (i, data) = take(length)(i)?;
+    Ok((i, XferSegmentRef {
+        flags,
+        reserved_flags: bits & !XferSegmentFlags::all().bits(),
+        transfer_id,
+        extensions: extensions.unwrap_or_default(),
+        data }))
+}
+fn xfer_segment(i: &[u8]) -> IResult<&[u8], XferSegment, TcpclError> {
+    map(|i| xfer_segment_ref(i, MAX_SEGMENT_LEN), XferSegmentRef::into_owned)(i)
+}
+
+
+#[derive(Debug)]
+/// Reassembles the data of received XFER_SEGMENTs into complete transfers
+pub struct TransferReassembler {
+    transfer_mru: u64,
+    max_in_progress: usize,
+    transfers: HashMap<u64, Vec<u8>>,
+}
+
+impl TransferReassembler {
+    /// Create a new reassembler accepting transfers up to `transfer_mru` bytes
+    ///
+    /// At most `MAX_TRANSFERS_IN_PROGRESS` transfers are kept in progress at once.
+    pub fn new(transfer_mru: u64) -> TransferReassembler {
+        TransferReassembler {
+            transfer_mru,
+            max_in_progress: MAX_TRANSFERS_IN_PROGRESS,
+            transfers: HashMap::new(),
+        }
+    }
+
+    /// Set the maximum number of transfers in progress at once
+    ///
+    /// Together with the transfer mru this bounds the memory a peer can make the
+    /// reassembler hold.
+    pub fn max_in_progress(&mut self, max_in_progress: usize) -> &mut TransferReassembler {
+        self.max_in_progress = max_in_progress;
+        self
+    }
+
+    /// Add a received segment to its transfer
+    ///
+    /// Returns the data of the transfer once the segment with END arrives. A single segment
+    /// with both START and END forms a complete transfer, which may be empty.
+    ///
+    /// # Errors
+    /// If a segment with START arrives for a transfer in progress an Error is returned.
+    /// If a segment with START arrives while the maximum number of transfers is in progress
+    /// an Error is returned.
+    /// If a segment without START arrives for a transfer not in progress an Error is returned.
+    /// If the transfer grows beyond the transfer mru an Error is returned and the transfer
+    /// is dropped.
+    pub fn push(&mut self, segment: XferSegment) -> Result<Option<Vec<u8>>, TcpclError> {
+        let id = segment.transfer_id;
+        if segment.flags.contains(START) {
+            if self.transfers.contains_key(&id) {
+                return Err(TcpclError::TransferAlreadyStarted(id));
+            }
+            if self.transfers.len() >= self.max_in_progress {
+                return Err(TcpclError::TooManyTransfers(id));
+            }
+            self.transfers.insert(id, Vec::new());
+        }
+        let data = self.transfers.get_mut(&id).ok_or(TcpclError::TransferNotStarted(id))?;
+        if (data.len() + segment.data.len()) as u64 > self.transfer_mru {
+            self.transfers.remove(&id);
+            return Err(TcpclError::TransferTooLong(id));
+        }
+        data.extend_from_slice(&segment.data);
+        if segment.flags.contains(END) {
+            return Ok(self.transfers.remove(&id));
+        }
+        Ok(None)
+    }
+
+    /// Get the number of transfers in progress
+    pub fn in_progress(&self) -> usize {
+        self.transfers.len()
+    }
+}
+
+
+#[derive(Debug, Default)]
+/// Hands out the ids of transfers initiated by this node
+///
+/// Ids start at 0 and increase by one for each transfer. The allocator does not wrap
+/// around: after `u64::MAX` has been handed out every further call fails, since reusing an
+/// id within a session is not allowed.
+pub struct TransferIdAllocator {
+    next_id: u64,
+    exhausted: bool,
+}
+
+impl TransferIdAllocator {
+    /// Create a new allocator starting at id 0
+    pub fn new() -> TransferIdAllocator {
+        TransferIdAllocator::default()
+    }
+
+    /// Get the id for the next transfer
+    ///
+    /// # Errors
+    /// If all ids have been handed out an Error is returned.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<u64, TcpclError> {
+        if self.exhausted {
+            return Err(TcpclError::TransferIdsExhausted);
+        }
+        let id = self.next_id;
+        match id.checked_add(1) {
+            Some(next_id) => self.next_id = next_id,
+            None => self.exhausted = true,
+        }
+        Ok(id)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// XFER_ACK message
+pub struct XferAck {
+    flags: XferSegmentFlags,
+    reserved_flags: u8,
+    transfer_id: u64,
+    acknowledged_length: u64,
+}
+
+impl XferAck {
+    /// Create a new XFER_ACK
+    pub fn new(flags: XferSegmentFlags, transfer_id: u64, acknowledged_length: u64) -> XferAck {
+        XferAck {
+            flags,
+            reserved_flags: 0,
+            transfer_id,
+            acknowledged_length,
+        }
+    }
+
+    /// Get the flags of the acknowledged XFER_SEGMENT
+    pub fn flags(&self) -> XferSegmentFlags {
+        self.flags
+    }
+
+    /// Get the reserved bits that were set in the flags of a received XFER_ACK
+    ///
+    /// Reserved bits are never serialized.
+    pub fn reserved_flags(&self) -> u8 {
+        self.reserved_flags
+    }
+
+    /// Get the transfer id of the XFER_ACK
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    /// Get the number of bytes of the transfer received so far
+    pub fn acknowledged_length(&self) -> u64 {
+        self.acknowledged_length
+    }
+
+    /// Serialize the XFER_ACK to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(18);
+        buffer.write_u8(MSG_TYPE_XFER_ACK).unwrap();
+        buffer.write_u8(self.flags.bits()).unwrap();
+        buffer.write_u64::<BigEndian>(self.transfer_id).unwrap();
+        buffer.write_u64::<BigEndian>(self.acknowledged_length).unwrap();
+        buffer
+    }
+
+    /// Parse the XFER_ACK from a byte slice
+    ///
+    /// Reserved flags are ignored, they are available from `reserved_flags`.
+    ///
+    /// # Errors
+    /// If the message type is not XFER_ACK an Error is returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], XferAck, TcpclError> {
+        xfer_ack(i)
+    }
+}
+
+fn xfer_ack(i: &[u8]) -> IResult<&[u8], XferAck, TcpclError> {
+    let (i, _) = tag([MSG_TYPE_XFER_ACK])(i)?;
+    let (i, bits) = be_u8(i)?;
+    let (i, transfer_id) = be_u64(i)?;
+    let (i, acknowledged_length) = be_u64(i)?;
+    Ok((i, XferAck {
+        flags: XferSegmentFlags::from_bits_truncate(bits),
+        reserved_flags: bits & !XferSegmentFlags::all().bits(),
+        transfer_id,
+        acknowledged_length }))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reason for refusing a transfer
+pub enum XferRefuseReason {
+    /// Reason unknown or not specified
+    Unknown = 0x00,
+    /// The receiver already has the complete bundle
+    Completed = 0x01,
+    /// The receiver's resources are exhausted
+    NoResources = 0x02,
+    /// The receiver has encountered a problem and asks for retransmission
+    Retransmit = 0x03,
+    /// The receiver is not willing to accept the bundle
+    NotAcceptable = 0x04,
+    /// The receiver cannot process a transfer Extension Item
+    ExtensionFailure = 0x05,
+}
+
+impl XferRefuseReason {
+    /// Try and map a reason code octet to a reason
+    pub fn from_u8(code: u8) -> Option<XferRefuseReason> {
+        match code {
+            0x00 => Some(XferRefuseReason::Unknown),
+            0x01 => Some(XferRefuseReason::Completed),
+            0x02 => Some(XferRefuseReason::NoResources),
+            0x03 => Some(XferRefuseReason::Retransmit),
+            0x04 => Some(XferRefuseReason::NotAcceptable),
+            0x05 => Some(XferRefuseReason::ExtensionFailure),
+            _ => None,
+        }
+    }
+
+    /// Try and map a reason code octet to a reason
+    ///
+    /// # Errors
+    /// If the reason code is not defined an Error naming the code is returned.
+    pub fn from_u8_strict(code: u8) -> Result<XferRefuseReason, TcpclError> {
+        XferRefuseReason::from_u8(code)
+            .ok_or(TcpclError::UnknownRefuseReason(code))
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// XFER_REFUSE message
+pub struct XferRefuse {
+    reason: XferRefuseReason,
+    transfer_id: u64,
+}
+
+impl XferRefuse {
+    /// Create a new XFER_REFUSE
+    pub fn new(reason: XferRefuseReason, transfer_id: u64) -> XferRefuse {
+        XferRefuse {
+            reason,
+            transfer_id,
+        }
+    }
+
+    /// Get the reason of the XFER_REFUSE
+    pub fn reason(&self) -> XferRefuseReason {
+        self.reason
+    }
+
+    /// Get the transfer id of the XFER_REFUSE
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    /// Serialize the XFER_REFUSE to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(10);
+        buffer.write_u8(MSG_TYPE_XFER_REFUSE).unwrap();
+        buffer.write_u8(self.reason as u8).unwrap();
+        buffer.write_u64::<BigEndian>(self.transfer_id).unwrap();
+        buffer
+    }
+
+    /// Parse the XFER_REFUSE from a byte slice
+    ///
+    /// # Errors
+    /// If the message type is not XFER_REFUSE an Error is returned.
+    /// If the reason code is unknown an Error is returned, see
+    /// `XferRefuseReason::from_u8_strict`.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], XferRefuse, TcpclError> {
+        xfer_refuse(i)
+    }
+}
+
+fn xfer_refuse(i: &[u8]) -> IResult<&[u8], XferRefuse, TcpclError> {
+    let (i, _) = tag([MSG_TYPE_XFER_REFUSE])(i)?;
+    let (i, reason) = map_res(be_u8, XferRefuseReason::from_u8_strict)(i)?;
+    let (i, transfer_id) = be_u64(i)?;
+    Ok((i, XferRefuse {
+        reason,
+        transfer_id }))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Reason for rejecting a message
+pub enum MsgRejectReason {
+    /// The message type is unknown
+    MessageTypeUnknown = 0x01,
+    /// The message type is known but not supported
+    MessageUnsupported = 0x02,
+    /// The message is not expected in the current state of the session
+    MessageUnexpected = 0x03,
+}
+
+impl MsgRejectReason {
+    /// Try and map a reason code octet to a reason
+    pub fn from_u8(code: u8) -> Option<MsgRejectReason> {
+        match code {
+            0x01 => Some(MsgRejectReason::MessageTypeUnknown),
+            0x02 => Some(MsgRejectReason::MessageUnsupported),
+            0x03 => Some(MsgRejectReason::MessageUnexpected),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// MSG_REJECT message
+pub struct MsgReject {
+    reason: MsgRejectReason,
+    rejected_message_header: u8,
+}
+
+impl MsgReject {
+    /// Create a new MSG_REJECT
+    pub fn new(reason: MsgRejectReason, rejected_message_header: u8) -> MsgReject {
+        MsgReject {
+            reason,
+            rejected_message_header,
+        }
+    }
+
+    /// Create the MSG_REJECT answering a message with an unknown message type
+    pub fn unknown_type(rejected_message_header: u8) -> MsgReject {
+        MsgReject::new(MsgRejectReason::MessageTypeUnknown, rejected_message_header)
+    }
+
+    /// Get the reason of the MSG_REJECT
+    pub fn reason(&self) -> MsgRejectReason {
+        self.reason
+    }
+
+    /// Get the message type octet of the rejected message
+    pub fn rejected_message_header(&self) -> u8 {
+        self.rejected_message_header
+    }
+
+    /// Serialize the MSG_REJECT to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        vec![MSG_TYPE_MSG_REJECT, self.reason as u8, self.rejected_message_header]
+    }
+
+    /// Parse the MSG_REJECT from a byte slice
+    ///
+    /// # Errors
+    /// If the message type is not MSG_REJECT an Error is returned.
+    /// If the reason code is unknown an Error is returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], MsgReject, TcpclError> {
+        msg_reject(i)
+    }
+}
+
+fn msg_reject(i: &[u8]) -> IResult<&[u8], MsgReject, TcpclError> {
+    let (i, _) = tag([MSG_TYPE_MSG_REJECT])(i)?;
+    let (i, reason) = map_opt(be_u8, MsgRejectReason::from_u8)(i)?;
+    let (i, rejected_message_header) = be_u8(i)?;
+    Ok((i, MsgReject {
+        reason,
+        rejected_message_header }))
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Any message exchanged after the Contact Header
+pub enum Message {
+    /// SESS_INIT message
+    SessInit(SessInit),
+    /// SESS_TERM message
+    SessTerm(SessTerm),
+    /// KEEPALIVE message
+    Keepalive(Keepalive),
+    /// XFER_SEGMENT message
+    XferSegment(XferSegment),
+    /// XFER_ACK message
+    XferAck(XferAck),
+    /// XFER_REFUSE message
+    XferRefuse(XferRefuse),
+    /// MSG_REJECT message
+    MsgReject(MsgReject),
+}
+
+impl Message {
+    /// Serialize the message to a byte vector
+    pub fn serialize(&self) -> Vec<u8> {
+        match *self {
+            Message::SessInit(ref message) => message.serialize(),
+            Message::SessTerm(ref message) => message.serialize(),
+            Message::Keepalive(ref message) => message.serialize(),
+            Message::XferSegment(ref message) => message.serialize(),
+            Message::XferAck(ref message) => message.serialize(),
+            Message::XferRefuse(ref message) => message.serialize(),
+            Message::MsgReject(ref message) => message.serialize(),
+        }
+    }
+
+    /// Parse the next message from a byte slice based on its message type octet
+    ///
+    /// # Errors
+    /// If the message type is unknown, `TcpclError::UnknownMessageType` is returned so the
+    /// caller can answer with `MsgReject::unknown_type`.
+    /// Otherwise the errors of the parser for the message type are returned.
+    pub fn deserialize(i: &[u8]) -> IResult<&[u8], Message, TcpclError> {
+        message(i)
+    }
+
+    /// Serialize the message prefixed with its length as a big-endian u32
+    ///
+    /// Like `ContactHeader::serialize_framed` this framing is not part of tcpcl, it is meant
+    /// for storing or debugging messages.
+    pub fn serialize_framed(&self) -> Vec<u8> {
+        frame(self.serialize())
+    }
+
+    /// Parse a message written by `serialize_framed`
+    ///
+    /// # Errors
+    /// The same errors as `deserialize` are returned. Additionally an Error is returned if
+    /// the message does not fill the frame exactly. A message cut short by the end of the
+    /// frame is reported as `TcpclError::Incomplete`.
+    pub fn deserialize_framed(i: &[u8]) -> IResult<&[u8], Message, TcpclError> {
+        framed_message(i)
+    }
+}
+
+/// Prefix a serialized message with its length as a big-endian u32
+fn frame(message: Vec<u8>) -> Vec<u8> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(4 + message.len());
+    buffer.write_u32::<BigEndian>(message.len() as u32).unwrap();
+    buffer.extend(message);
+    buffer
+}
+
+/// Parse a message that has to fill a length-delimited frame exactly
+fn framed_message(i: &[u8]) -> IResult<&[u8], Message, TcpclError> {
+    let (rest, frame) = length_data(be_u32)(i)?;
+    match message(frame) {
+        Ok((remaining, message)) => {
+            if remaining.is_empty() {
+                Ok((rest, message))
+            } else {
+                Err(nom::Err::Error(TcpclError::TrailingBytes(remaining.len())))
+            }
+        }
+        Err(nom::Err::Incomplete(_)) => Err(nom::Err::Error(TcpclError::Incomplete)),
+        Err(e) => Err(e),
+    }
+}
+
+fn message(i: &[u8]) -> IResult<&[u8], Message, TcpclError> {
+    let (_, msg_type) = peek(be_u8)(i)?;
+    match msg_type {
+        MSG_TYPE_XFER_SEGMENT => map(xfer_segment, Message::XferSegment)(i),
+        MSG_TYPE_XFER_ACK => map(xfer_ack, Message::XferAck)(i),
+        MSG_TYPE_XFER_REFUSE => map(xfer_refuse, Message::XferRefuse)(i),
+        MSG_TYPE_KEEPALIVE => map(keepalive, Message::Keepalive)(i),
+        MSG_TYPE_SESS_TERM => map(sess_term, Message::SessTerm)(i),
+        MSG_TYPE_MSG_REJECT => map(msg_reject, Message::MsgReject)(i),
+        MSG_TYPE_SESS_INIT => map(sess_init, Message::SessInit)(i),
+        _ => Err(nom::Err::Error(TcpclError::UnknownMessageType(msg_type))),
+    }
+}
+
+
+/// Calculate the number of XFER_SEGMENTs needed to send a bundle
+///
+/// An empty bundle still requires a single segment carrying both START and END.
+///
+/// # Errors
+/// If the segment mru is zero an Error is returned.
+pub fn segment_count(bundle_len: u64, segment_mru: u64) -> Result<u64, TcpclError> {
+    if segment_mru == 0 {
+        return Err(TcpclError::ZeroSegmentMru);
+    }
+    Ok(std::cmp::max(1, bundle_len.div_ceil(segment_mru)))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Result of negotiating the keepalive interval between two peers
+pub struct KeepaliveNegotiation {
+    interval: u16,
+    asymmetric: bool,
+}
+
+impl KeepaliveNegotiation {
+    /// Get the effective keepalive interval in seconds, 0 means keepalives are disabled
+    pub fn interval(&self) -> u16 {
+        self.interval
+    }
+
+    /// Check whether the advertised intervals differ significantly
+    pub fn is_asymmetric(&self) -> bool {
+        self.asymmetric
+    }
+
+    /// Check the effective keepalive interval against a maximum in seconds
+    ///
+    /// A disabled keepalive (interval 0) is not affected by the maximum.
+    ///
+    /// # Errors
+    /// If the effective interval is larger than `max` an Error is returned.
+    pub fn enforce_max_keepalive(&self, max: u16) -> Result<(), TcpclError> {
+        if self.interval > max {
+            return Err(TcpclError::KeepaliveTooLong {
+                interval: self.interval,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Negotiate the keepalive interval from the locally and remotely advertised intervals
+///
+/// The effective interval is the minimum of both, or 0 if either side disabled keepalives.
+/// The advertisement is considered asymmetric if only one side disabled keepalives, or if
+/// the larger interval is more than twice the smaller one. In that case the peer with the
+/// larger interval will see traffic more often than it asked for, which is worth a warning.
+pub fn keepalive_negotiation(local: u16, remote: u16) -> KeepaliveNegotiation {
+    let lower = std::cmp::min(local, remote);
+    let upper = std::cmp::max(local, remote);
+    KeepaliveNegotiation {
+        interval: lower,
+        asymmetric: upper as u32 > lower as u32 * KEEPALIVE_ASYMMETRY_RATIO,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Session parameters negotiated from the Contact Headers of both peers
+pub struct NegotiatedParams {
+    keepalive: u16,
+    use_tls: bool,
+    segment_mru: u64,
+    transfer_mru: u64,
+}
+
+impl NegotiatedParams {
+    /// Get the negotiated keepalive interval in seconds, 0 means keepalives are disabled
+    pub fn keepalive(&self) -> u16 {
+        self.keepalive
+    }
+
+    /// Check whether TLS is used for the session
+    pub fn use_tls(&self) -> bool {
+        self.use_tls
+    }
+
+    /// Get the largest segment the peer accepts
+    pub fn segment_mru(&self) -> u64 {
+        self.segment_mru
+    }
+
+    /// Get the largest transfer the peer accepts
+    pub fn transfer_mru(&self) -> u64 {
+        self.transfer_mru
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Timer deciding when to send a KEEPALIVE and when the session is dead
+///
+/// A KEEPALIVE has to be sent if no message was sent within the interval. The session is
+/// considered dead if no message was received within twice the interval. An interval of 0
+/// disables both. The timer never reads the clock itself, the current time is always passed
+/// in by the caller.
+pub struct KeepaliveTimer {
+    interval: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+}
+
+impl KeepaliveTimer {
+    /// Create a new timer for the negotiated interval in seconds, starting at `now`
+    pub fn new(interval: u16, now: Instant) -> KeepaliveTimer {
+        KeepaliveTimer {
+            interval: Duration::from_secs(interval as u64),
+            last_sent: now,
+            last_received: now,
+        }
+    }
+
+    /// Check whether a KEEPALIVE has to be sent
+    pub fn should_send(&self, now: Instant) -> bool {
+        self.interval != Duration::from_secs(0)
+            && now.saturating_duration_since(self.last_sent) >= self.interval
+    }
+
+    /// Record that a message was sent at `now`
+    pub fn record_sent(&mut self, now: Instant) {
+        self.last_sent = now;
+    }
+
+    /// Record that a message was received at `now`
+    pub fn record_received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    /// Check whether nothing was received for twice the interval
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.interval != Duration::from_secs(0)
+            && now.saturating_duration_since(self.last_received) >= self.interval * 2
+    }
+}
+
+
+/// Compute a fingerprint of the session parameters negotiated between two Contact Headers
+///
+/// The fingerprint covers the negotiated keepalive, the MRUs and the eids of both sides.
+/// The parameters of each side are encoded separately and hashed in sorted order, so both
+/// peers of a session compute the same fingerprint no matter which side is `local`.
+/// The version is excluded so the fingerprint stays the same across compatible versions.
+/// It is computed with FNV-1a and does not change between builds or platforms, which makes
+/// it usable as a key for caching TLS sessions or reusing connections.
+pub fn parameter_fingerprint(local: &ContactHeader, remote: &ContactHeader) -> u64 {
+    let keepalive = keepalive_negotiation(local.keepalive, remote.keepalive).interval();
+    let mut sides: Vec<Vec<u8>> = [local, remote].iter().map(|header| {
+        let mut side: Vec<u8> = Vec::new();
+        side.write_u64::<BigEndian>(header.segment_mru).unwrap();
+        side.write_u64::<BigEndian>(header.transfer_mru).unwrap();
+        match header.eid.as_ref() {
+            Some(eid) => {
+                side.write_u8(1).unwrap();
+                side.write_u16::<BigEndian>(eid.len() as u16).unwrap();
+                side.extend(eid.as_bytes());
+            }
+            None => side.write_u8(0).unwrap(),
+        }
+        side
+    }).collect();
+    sides.sort();
+    let mut buffer: Vec<u8> = Vec::new();
+    buffer.write_u16::<BigEndian>(keepalive).unwrap();
+    for side in &sides {
+        buffer.extend(side);
+    }
+    buffer.iter()
+        .fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Pacing for segment emission on rate-limited links
+///
+/// The limiter does not sleep or schedule anything itself, it only tells the caller when
+/// the next write may happen so it can be used with any runtime.
+pub struct RateLimiter {
+    bytes_per_second: u64,
+}
+
+impl RateLimiter {
+    /// Create a new rate limiter, a rate of 0 disables limiting
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        RateLimiter { bytes_per_second }
+    }
+
+    /// Get the earliest time the next segment may be written after writing a segment of
+    /// `segment_len` bytes at `now`
+    pub fn next_send_time(&self, segment_len: usize, now: Instant) -> Instant {
+        if self.bytes_per_second == 0 {
+            return now;
+        }
+        let nanos = segment_len as u128 * 1_000_000_000 / self.bytes_per_second as u128;
+        now + Duration::from_nanos(std::cmp::min(nanos, u64::MAX as u128) as u64)
+    }
+}
+
+
+#[derive(Debug)]
+/// Serialized message that may be written out over several partial writes
+///
+/// This is intended for non-blocking sinks that only accept part of a message per call.
+pub struct PendingWrite {
+    buffer: Vec<u8>,
+    written: usize,
+}
+
+impl PendingWrite {
+    /// Create a new pending write for an already serialized message
+    pub fn new(buffer: Vec<u8>) -> PendingWrite {
+        PendingWrite {
+            buffer,
+            written: 0,
+        }
+    }
+
+    /// Write as much of the remaining message as the sink accepts in a single call
+    ///
+    /// Returns the number of bytes written by this call. Once `is_complete` returns true
+    /// this always returns 0.
+    ///
+    /// # Errors
+    /// Errors of the sink are passed through, a `WouldBlock` leaves the progress unchanged.
+    /// If the sink accepts no bytes at all an Error of kind `WriteZero` is returned.
+    pub fn write_remaining<W: Write>(&mut self, w: &mut W) -> std::io::Result<usize> {
+        if self.is_complete() {
+            return Ok(0);
+        }
+        let count = w.write(self.remaining())?;
+        if count == 0 {
+            return Err(Error::new(ErrorKind::WriteZero, "failed to write pending message"));
+        }
+        self.written += count;
+        Ok(count)
+    }
+
+    /// Get the bytes that have not been written yet
+    pub fn remaining(&self) -> &[u8] {
+        &self.buffer[self.written..]
+    }
+
+    /// Get the number of bytes written so far
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Check whether the whole message has been written
+    pub fn is_complete(&self) -> bool {
+        self.written == self.buffer.len()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test simple creation of a default header
+    fn test_init_contact_header() {
+        let contact_header = ContactHeader::new();
+        assert_eq!(contact_header.eid, None);
+        assert_eq!(contact_header.version, 4);
+        assert_eq!(contact_header.flags, super::ContactHeaderFlags::empty());
+        assert_eq!(contact_header.transfer_mru, 0);
+        assert_eq!(contact_header.segment_mru, 0);
+    }
+
+    #[test]
+    /// Test setting and unsetting a flag
+    fn test_set_unset_flag() {
+        let mut contact_header = ContactHeader::new();
+        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+        contact_header.set_flag(CAN_TLS);
+        assert_eq!(contact_header.flags.bits(), 0x01);
+        assert_eq!(contact_header.flags, CAN_TLS);
+        contact_header.unset_flag(CAN_TLS);
+        assert_eq!(contact_header.flags.bits(), 0x00);
+        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+    }
+
+    #[test]
+    /// Test clearing the bitfield
+    fn test_set_clear_flag() {
+        let mut contact_header = ContactHeader::new();
+        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+        contact_header.set_flag(CAN_TLS);
+        assert_eq!(contact_header.flags, CAN_TLS);
+        contact_header.clear_flags();
+        assert_eq!(contact_header.flags.bits(), 0x00);
+        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+    }
+
+    #[test]
+    /// Test setting the same flags twice
+    ///
+    /// This should behave the same way as setting it only once.
+    fn test_duplicate_set() {
+        let mut contact_header = ContactHeader::new();
+        assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
+        contact_header.set_flag(CAN_TLS);
+        assert_eq!(contact_header.flags, CAN_TLS);
+        contact_header.set_flag(CAN_TLS);
         assert_eq!(contact_header.flags, CAN_TLS);
         contact_header.unset_flag(CAN_TLS);
         assert_eq!(contact_header.flags, ContactHeaderFlags::empty());
     }
+
+    #[test]
+    /// Test merging and intersecting flag sets
+    fn test_merge_intersect_flags() {
+        let empty = ContactHeaderFlags::empty();
+        assert_eq!(CAN_TLS.merge(empty), CAN_TLS);
+        assert_eq!(empty.merge(CAN_TLS), CAN_TLS);
+        assert_eq!(empty.merge(empty), empty);
+        assert_eq!(CAN_TLS.intersect(empty), empty);
+        assert_eq!(empty.intersect(CAN_TLS), empty);
+        assert_eq!(CAN_TLS.intersect(CAN_TLS), CAN_TLS);
+    }
+
+    fn respond_with(allow_tls: bool, peer_flags: ContactHeaderFlags) -> ContactHeader {
+        let mut local = ContactHeader::new();
+        local.keepalive(30).eid("local").unwrap();
+        let mut config = NodeConfig::new(local);
+        config.allow_tls(allow_tls);
+        let mut peer = ContactHeader::new();
+        peer.flags(peer_flags);
+        ContactHeader::respond_to(&peer, &config)
+    }
+
+    #[test]
+    /// Test that CAN_TLS is only mirrored when both sides allow it
+    fn test_respond_to_tls() {
+        assert_eq!(respond_with(true, CAN_TLS).flags, CAN_TLS);
+        assert_eq!(respond_with(true, ContactHeaderFlags::empty()).flags,
+                   ContactHeaderFlags::empty());
+        assert_eq!(respond_with(false, CAN_TLS).flags, ContactHeaderFlags::empty());
+        assert_eq!(respond_with(false, ContactHeaderFlags::empty()).flags,
+                   ContactHeaderFlags::empty());
+    }
+
+    #[test]
+    /// Test that the response is built from the configured template
+    fn test_respond_to_template() {
+        let response = respond_with(true, CAN_TLS);
+        assert_eq!(response.keepalive, 30);
+        assert_eq!(response.eid, Some("local".to_string()));
+        assert_eq!(response.version, 4);
+    }
+
+    #[test]
+    /// Test that the Contact Header only carries magic, version and flags under RFC 9174
+    fn test_variant_length() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).eid("localhost").unwrap();
+        assert_eq!(contact_header.serialize().len(), CONTACT_HEADER_BASE_LENGTH + 2 + 9);
+        contact_header.variant(ProtocolVariant::Rfc9174).set_flag(CAN_TLS);
+        let buffer = contact_header.serialize();
+        assert_eq!(buffer, vec![0x64, 0x74, 0x6e, 0x21, 0x04, 0x01]);
+        assert_eq!(buffer.len(), CONTACT_HEADER_RFC9174_LENGTH);
+        match ContactHeader::deserialize_variant(&buffer, ProtocolVariant::Rfc9174) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed.flags, CAN_TLS);
+                assert_eq!(parsed.variant, ProtocolVariant::Rfc9174);
+            }
+            _ => panic!("failed to parse RFC 9174 contact header"),
+        }
+    }
+
+    #[test]
+    /// Test that keepalive, MRUs and eid do not roundtrip under RFC 9174
+    fn test_variant_drops_session_parameters() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.variant(ProtocolVariant::Rfc9174)
+            .keepalive(30)
+            .segment_mru(1024)
+            .transfer_mru(4096)
+            .eid("dtn://a/").unwrap();
+        let buffer = contact_header.serialize();
+        let (_, parsed) = ContactHeader::deserialize_variant(&buffer, ProtocolVariant::Rfc9174)
+            .unwrap();
+        assert_ne!(parsed, contact_header);
+        assert_eq!(parsed.get_keepalive(), 0);
+        assert_eq!(parsed.get_segment_mru(), 0);
+        assert_eq!(parsed.get_transfer_mru(), 0);
+        assert_eq!(parsed.get_eid(), None);
+    }
+
+    #[test]
+    /// Test that the readers parse the RFC 9174 layout when asked for it
+    fn test_variant_read_side() {
+        let mut local = ContactHeader::new();
+        local.variant(ProtocolVariant::Rfc9174);
+        let mut peer = ContactHeader::new();
+        peer.variant(ProtocolVariant::Rfc9174).set_flag(CAN_TLS);
+        let buffer = peer.serialize();
+
+        let mut stream = MockStream {
+            input: std::io::Cursor::new(buffer.clone()),
+            output: Vec::new(),
+        };
+        assert_eq!(perform_contact_exchange(&mut stream, &local, 64).unwrap(), peer);
+        assert_eq!(stream.output.len(), CONTACT_HEADER_RFC9174_LENGTH);
+
+        let mut decoder = ContactHeaderDecoder::new();
+        decoder.variant(ProtocolVariant::Rfc9174);
+        decoder.push(&buffer);
+        assert_eq!(decoder.try_parse().unwrap().unwrap(), peer);
+
+        let mut out = local.clone();
+        assert_eq!(ContactHeader::deserialize_into(&buffer, &mut out).unwrap(), buffer.len());
+        assert_eq!(out, peer);
+    }
+
+    /// Writer that accepts at most `limit` bytes per call
+    struct LimitedWriter {
+        limit: usize,
+        data: Vec<u8>,
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let count = std::cmp::min(self.limit, buf.len());
+            self.data.extend_from_slice(&buf[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Test resuming a write into a sink that only accepts a few bytes per call
+    fn test_pending_write_partial() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.eid("localhost").unwrap();
+        let expected = contact_header.serialize();
+        let mut pending = contact_header.pending_write();
+        let mut writer = LimitedWriter { limit: 10, data: Vec::new() };
+        let mut calls = 0;
+        while !pending.is_complete() {
+            let count = pending.write_remaining(&mut writer).unwrap();
+            assert!(count <= 10);
+            calls += 1;
+        }
+        assert_eq!(calls, 4);
+        assert_eq!(pending.written(), expected.len());
+        assert_eq!(pending.write_remaining(&mut writer).unwrap(), 0);
+        assert_eq!(writer.data, expected);
+    }
+
+    #[test]
+    /// Test segment counts for bundles that fill the last segment exactly and partially
+    fn test_segment_count() {
+        assert_eq!(segment_count(300, 100).unwrap(), 3);
+        assert_eq!(segment_count(301, 100).unwrap(), 4);
+        assert_eq!(segment_count(99, 100).unwrap(), 1);
+        assert_eq!(segment_count(u64::MAX, 1).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    /// Test that an empty bundle needs a single segment
+    fn test_segment_count_empty() {
+        assert_eq!(segment_count(0, 100).unwrap(), 1);
+    }
+
+    #[test]
+    /// Test that a zero segment mru is rejected
+    fn test_segment_count_zero_mru() {
+        match segment_count(100, 0) {
+            Err(TcpclError::ZeroSegmentMru) => (),
+            _ => panic!("zero segment mru must be an error"),
+        }
+    }
+
+    #[test]
+    /// Test that a Contact Header survives the length-delimited framing
+    fn test_framed_roundtrip() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).segment_mru(1024).eid("localhost").unwrap();
+        let mut buffer = contact_header.serialize_framed();
+        assert_eq!(&buffer[..4], &[0x00, 0x00, 0x00, 35]);
+        assert_eq!(&buffer[4..], contact_header.serialize().as_slice());
+        buffer.push(0xff);
+        match ContactHeader::deserialize_framed(&buffer) {
+            Ok((rest, parsed)) => {
+                assert_eq!(rest, &[0xff]);
+                assert_eq!(parsed.keepalive, 30);
+                assert_eq!(parsed.segment_mru, 1024);
+                assert_eq!(parsed.eid, Some("localhost".to_string()));
+            }
+            _ => panic!("failed to parse framed contact header"),
+        }
+    }
+
+    #[test]
+    /// Test that a frame with bytes beyond the Contact Header is rejected
+    fn test_framed_trailing_bytes() {
+        let message = ContactHeader::new().serialize();
+        let mut buffer = vec![0x00, 0x00, 0x00, message.len() as u8 + 1];
+        buffer.extend(message);
+        buffer.push(0x00);
+        assert!(ContactHeader::deserialize_framed(&buffer).is_err());
+    }
+
+    #[test]
+    /// Test that a XFER_SEGMENT survives the length-delimited framing
+    fn test_framed_segment_roundtrip() {
+        let mut segment = XferSegment::new(START | END, 7, vec![1, 2, 3]);
+        segment.add_extension(ExtensionItem::new(CRITICAL, 0x01, vec![0x02]).unwrap()).unwrap();
+        let message = Message::XferSegment(segment);
+        let mut buffer = message.serialize_framed();
+        assert_eq!(&buffer[..4], &(message.serialize().len() as u32).to_be_bytes());
+        buffer.push(0xff);
+        match Message::deserialize_framed(&buffer) {
+            Ok((rest, parsed)) => {
+                assert_eq!(rest, &[0xff]);
+                assert_eq!(parsed, message);
+            }
+            _ => panic!("failed to parse framed XFER_SEGMENT"),
+        }
+        buffer.pop();
+        buffer[3] -= 1;
+        match Message::deserialize_framed(&buffer[..buffer.len() - 1]) {
+            Err(nom::Err::Error(TcpclError::Incomplete)) => (),
+            other => panic!("segment cut short by its frame must be an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    /// Test keepalive negotiation with equal and close advertisements
+    fn test_keepalive_negotiation_symmetric() {
+        let negotiation = keepalive_negotiation(30, 30);
+        assert_eq!(negotiation.interval(), 30);
+        assert!(!negotiation.is_asymmetric());
+        let negotiation = keepalive_negotiation(30, 60);
+        assert_eq!(negotiation.interval(), 30);
+        assert!(!negotiation.is_asymmetric());
+        let negotiation = keepalive_negotiation(0, 0);
+        assert_eq!(negotiation.interval(), 0);
+        assert!(!negotiation.is_asymmetric());
+    }
+
+    #[test]
+    /// Test keepalive negotiation with very different advertisements
+    fn test_keepalive_negotiation_asymmetric() {
+        let negotiation = keepalive_negotiation(3600, 10);
+        assert_eq!(negotiation.interval(), 10);
+        assert!(negotiation.is_asymmetric());
+        let negotiation = keepalive_negotiation(30, 0);
+        assert_eq!(negotiation.interval(), 0);
+        assert!(negotiation.is_asymmetric());
+    }
+
+    #[test]
+    /// Test parsing into the same Contact Header twice
+    fn test_deserialize_into_reuse() {
+        let mut first = ContactHeader::new();
+        first.keepalive(10).transfer_mru(4096).set_flag(CAN_TLS);
+        first.eid("dtn://first/").unwrap();
+        let mut second = ContactHeader::new();
+        second.keepalive(20).eid("dtn://b/").unwrap();
+
+        let mut out = ContactHeader::new();
+        let mut buffer = first.serialize();
+        buffer.push(0x04);
+        assert_eq!(ContactHeader::deserialize_into(&buffer, &mut out).unwrap(),
+                   buffer.len() - 1);
+        assert_eq!(out.keepalive, 10);
+        assert_eq!(out.transfer_mru, 4096);
+        assert_eq!(out.flags, CAN_TLS);
+        assert_eq!(out.eid, Some("dtn://first/".to_string()));
+        let capacity = out.eid.as_ref().unwrap().capacity();
+
+        let buffer = second.serialize();
+        assert_eq!(ContactHeader::deserialize_into(&buffer, &mut out).unwrap(), buffer.len());
+        assert_eq!(out.keepalive, 20);
+        assert_eq!(out.transfer_mru, 0);
+        assert_eq!(out.flags, ContactHeaderFlags::empty());
+        assert_eq!(out.eid, Some("dtn://b/".to_string()));
+        assert_eq!(out.eid.as_ref().unwrap().capacity(), capacity);
+    }
+
+    #[test]
+    /// Test that a truncated Contact Header leaves the target untouched
+    fn test_deserialize_into_incomplete() {
+        let mut out = ContactHeader::new();
+        out.keepalive(10);
+        let buffer = ContactHeader::new().serialize();
+        match ContactHeader::deserialize_into(&buffer[..10], &mut out) {
+            Err(TcpclError::Incomplete) => (),
+            _ => panic!("truncated contact header must be incomplete"),
+        }
+        assert_eq!(out.keepalive, 10);
+    }
+
+    #[test]
+    /// Test that parse failures are mapped to the matching error
+    fn test_deserialize_into_errors() {
+        let mut out = ContactHeader::new();
+        let mut buffer = ContactHeader::new().serialize();
+        buffer[5] = 0x80;
+        match ContactHeader::deserialize_into(&buffer, &mut out) {
+            Err(TcpclError::UnknownFlags(0x80)) => (),
+            _ => panic!("unknown flags must be an error"),
+        }
+        buffer[4] = 0x03;
+        match ContactHeader::deserialize_into(&buffer, &mut out) {
+            Err(TcpclError::UnsupportedVersion(3)) => (),
+            _ => panic!("unsupported version must be an error"),
+        }
+        buffer[0] = 0x00;
+        match ContactHeader::deserialize_into(&buffer, &mut out) {
+            Err(TcpclError::BadMagic) => (),
+            _ => panic!("bad magic must be an error"),
+        }
+    }
+
+    #[test]
+    /// Test that an eid exceeding the u16 length field is rejected with its length
+    fn test_eid_too_long() {
+        let mut contact_header = ContactHeader::new();
+        let eid = "a".repeat(u16::MAX as usize + 1);
+        match contact_header.eid(eid) {
+            Err(TcpclError::EidTooLong(len)) => assert_eq!(len, 65536),
+            _ => panic!("over-long eid must be an error"),
+        }
+        assert!(contact_header.eid("a".repeat(u16::MAX as usize)).is_ok());
+    }
+
+    #[test]
+    /// Test that writing into a sink gives the same bytes as serialize
+    fn test_serialize_into() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.flags(CAN_TLS).keepalive(30).segment_mru(1024).transfer_mru(4096)
+            .eid("localhost").unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        contact_header.serialize_into(&mut buffer).unwrap();
+        assert_eq!(buffer, contact_header.serialize());
+        contact_header.variant(ProtocolVariant::Rfc9174);
+        let mut buffer: Vec<u8> = Vec::new();
+        contact_header.serialize_into(&mut buffer).unwrap();
+        assert_eq!(buffer, contact_header.serialize());
+    }
+
+    #[test]
+    /// Test feeding a Contact Header to the decoder one byte at a time
+    fn test_decoder_byte_by_byte() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).eid("localhost").unwrap();
+        let buffer = contact_header.serialize();
+        let mut decoder = ContactHeaderDecoder::new();
+        let mut headers = Vec::new();
+        for byte in &buffer {
+            decoder.push(&[*byte]);
+            if let Some(result) = decoder.try_parse() {
+                headers.push(result.unwrap());
+            }
+        }
+        decoder.push(&[0x04]);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].get_keepalive(), 30);
+        assert_eq!(headers[0].get_eid(), Some("localhost"));
+        assert_eq!(decoder.buffered(), &[0x04]);
+    }
+
+    #[test]
+    /// Test that an invalid Contact Header is reported by the decoder
+    fn test_decoder_bad_magic() {
+        let mut decoder = ContactHeaderDecoder::new();
+        decoder.push(&[0x64, 0x74]);
+        assert!(decoder.try_parse().is_none());
+        decoder.push(&[0x00]);
+        match decoder.try_parse() {
+            Some(Err(TcpclError::BadMagic)) => (),
+            _ => panic!("invalid magic must be an error"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "smallvec")]
+    /// Test that a short eid stays inline and a long eid spills to the heap
+    fn test_serialize_small() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.eid("localhost").unwrap();
+        let small = contact_header.serialize_small();
+        assert!(!small.spilled());
+        assert_eq!(&small[..], &contact_header.serialize()[..]);
+
+        contact_header.eid("a".repeat(100)).unwrap();
+        let large = contact_header.serialize_small();
+        assert!(large.spilled());
+        assert_eq!(&large[..], &contact_header.serialize()[..]);
+    }
+
+    #[test]
+    /// Test that resync finds a Contact Header behind garbage
+    fn test_resync() {
+        let mut buffer = vec![0x00, 0x64, 0x74, 0xff, 0x21];
+        let mut contact_header = ContactHeader::new();
+        contact_header.eid("localhost").unwrap();
+        buffer.extend_from_slice(&contact_header.serialize());
+        let offset = resync(&buffer).unwrap();
+        assert_eq!(offset, 5);
+        let (_, parsed) = ContactHeader::deserialize(&buffer[offset..]).unwrap();
+        assert_eq!(parsed.get_eid(), Some("localhost"));
+        assert_eq!(resync(&[0x64, 0x74, 0x6e]), None);
+    }
+
+    #[test]
+    /// Test that a fully populated Contact Header survives a roundtrip unchanged
+    fn test_contact_header_roundtrip_eq() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.flags(CAN_TLS)
+            .keepalive(30)
+            .segment_mru(1 << 20)
+            .transfer_mru(1 << 32)
+            .eid("dtn://node/").unwrap();
+        let bytes = contact_header.serialize();
+        assert_eq!(contact_header, ContactHeader::deserialize(&bytes).unwrap().1);
+    }
+
+    #[test]
+    /// Test that a cloned Contact Header serializes to identical bytes
+    fn test_contact_header_clone() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.flags(CAN_TLS).keepalive(15).eid("localhost").unwrap();
+        let clone = contact_header.clone();
+        assert_eq!(clone.serialize(), contact_header.serialize());
+    }
+
+    #[test]
+    /// Test that the default Contact Header matches new()
+    fn test_contact_header_default() {
+        assert_eq!(ContactHeader::default(), ContactHeader::new());
+        assert_eq!(ContactHeader::default().version(), 4);
+    }
+
+    #[test]
+    /// Test the human-readable summary of a Contact Header
+    fn test_contact_header_display() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).segment_mru(65536).transfer_mru(1048576);
+        assert_eq!(format!("{}", contact_header),
+                   "TCPCLv4 ContactHeader(eid=<none>, keepalive=30s, segment_mru=65536, \
+                   transfer_mru=1048576, flags=[])");
+        contact_header.flags(CAN_TLS).eid("localhost").unwrap();
+        let summary = format!("{}", contact_header);
+        assert!(summary.contains("CAN_TLS"));
+        assert!(summary.contains("eid=\"localhost\""));
+    }
+
+    #[test]
+    /// Test that diff reports exactly the fields that differ
+    fn test_contact_header_diff() {
+        let mut local = ContactHeader::new();
+        local.flags(CAN_TLS).keepalive(30).eid("local").unwrap();
+        let mut remote = local.clone();
+        remote.keepalive(60).eid("remote").unwrap();
+        assert_eq!(local.diff(&remote), vec![
+            FieldDiff::Keepalive(30, 60),
+            FieldDiff::Eid(Some("local".to_string()), Some("remote".to_string())),
+        ]);
+        assert!(local.diff(&local).is_empty());
+
+        let mut rfc9174 = local.clone();
+        rfc9174.variant(ProtocolVariant::Rfc9174);
+        assert_ne!(local, rfc9174);
+        assert_eq!(local.diff(&rfc9174), vec![
+            FieldDiff::Variant(ProtocolVariant::Draft, ProtocolVariant::Rfc9174),
+        ]);
+    }
+
+    #[test]
+    /// Test that deserialize_exact rejects trailing bytes
+    fn test_deserialize_exact() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.eid("localhost").unwrap();
+        let mut buffer = contact_header.serialize();
+        assert_eq!(ContactHeader::deserialize_exact(&buffer).unwrap(), contact_header);
+        buffer.push(0x04);
+        match ContactHeader::deserialize_exact(&buffer) {
+            Err(TcpclError::TrailingBytes(1)) => (),
+            _ => panic!("trailing bytes must be an error"),
+        }
+    }
+
+    #[test]
+    /// Test serializing and parsing a list of a critical and a non-critical Extension Item
+    fn test_extension_list_roundtrip() {
+        let items = vec![
+            ExtensionItem::new(CRITICAL, 0x01, vec![1, 2]).unwrap(),
+            ExtensionItem::new(ExtensionFlags::empty(), 0x02, vec![]).unwrap(),
+        ];
+        let buffer = ExtensionItem::serialize_list(&items).unwrap();
+        assert_eq!(buffer, vec![0x00, 0x00, 0x00, 0x0c,
+                                0x01, 0x00, 0x01, 0x00, 0x02, 1, 2,
+                                0x00, 0x00, 0x02, 0x00, 0x00]);
+        let (rest, parsed) = ExtensionItem::deserialize_list(&buffer).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed, items);
+        assert!(parsed[0].is_critical());
+        assert!(!parsed[1].is_critical());
+    }
+
+    #[test]
+    /// Test that an oversized extension length is rejected before waiting for the items
+    fn test_extension_list_too_long() {
+        let mut buffer = Vec::new();
+        buffer.write_u32::<BigEndian>(MAX_EXTENSIONS_LEN + 1).unwrap();
+        match ExtensionItem::deserialize_list(&buffer) {
+            Err(nom::Err::Error(TcpclError::ExtensionsTooLong(len))) => {
+                assert_eq!(len, MAX_EXTENSIONS_LEN as usize + 1)
+            }
+            _ => panic!("extension length over MAX_EXTENSIONS_LEN must be an error"),
+        }
+        buffer.truncate(0);
+        buffer.write_u32::<BigEndian>(MAX_EXTENSIONS_LEN).unwrap();
+        assert!(ExtensionItem::deserialize_list(&buffer).unwrap_err().is_incomplete());
+    }
+
+    /// Offset and width of every multi-byte field of the Contact Header
+    const MULTI_BYTE_FIELDS: [(usize, usize); 4] = [(6, 2), (8, 8), (16, 8), (24, 2)];
+
+    /// Build a Contact Header with the field at `offset` byte-swapped or truncated
+    fn malformed_header(offset: usize, width: usize, truncate: bool) -> Vec<u8> {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(0x0102)
+            .segment_mru(0x0102_0304_0506_0708)
+            .transfer_mru(0x1112_1314_1516_1718)
+            .eid("dtn://a").unwrap();
+        let mut buffer = contact_header.serialize();
+        if truncate {
+            buffer.truncate(offset + width / 2);
+        } else {
+            buffer[offset..offset + width].reverse();
+        }
+        buffer
+    }
+
+    #[test]
+    /// Test that a truncated multi-byte field is reported as incomplete
+    fn test_malformed_truncated_fields() {
+        for &(offset, width) in MULTI_BYTE_FIELDS.iter() {
+            match ContactHeader::deserialize_exact(&malformed_header(offset, width, true)) {
+                Err(TcpclError::Incomplete) => (),
+                other => panic!("field at {} must be incomplete, got {:?}", offset, other),
+            }
+        }
+    }
+
+    #[test]
+    /// Test that little-endian multi-byte fields are misread predictably
+    fn test_malformed_byte_order() {
+        let [keepalive, segment, transfer, eid] = MULTI_BYTE_FIELDS;
+        let parsed = ContactHeader::deserialize_exact(
+            &malformed_header(keepalive.0, keepalive.1, false)).unwrap();
+        assert_eq!(parsed.get_keepalive(), 0x0201);
+        let parsed = ContactHeader::deserialize_exact(
+            &malformed_header(segment.0, segment.1, false)).unwrap();
+        assert_eq!(parsed.get_segment_mru(), 0x0807_0605_0403_0201);
+        let parsed = ContactHeader::deserialize_exact(
+            &malformed_header(transfer.0, transfer.1, false)).unwrap();
+        assert_eq!(parsed.get_transfer_mru(), 0x1817_1615_1413_1211);
+        // An eid length of 7 read as 0x0700 runs past the end of the buffer
+        match ContactHeader::deserialize_exact(&malformed_header(eid.0, eid.1, false)) {
+            Err(TcpclError::Incomplete) => (),
+            other => panic!("swapped eid length must be incomplete, got {:?}", other),
+        }
+    }
+
+    #[test]
+    /// Test that a declared eid length is checked before any eid octets arrive
+    fn test_eid_length_cap() {
+        let mut buffer = ContactHeader::new().serialize();
+        buffer.truncate(CONTACT_HEADER_BASE_LENGTH);
+        buffer.write_u16::<BigEndian>(60000).unwrap();
+        match ContactHeader::deserialize(&buffer) {
+            Err(nom::Err::Incomplete(_)) => (),
+            _ => panic!("missing eid must be incomplete"),
+        }
+        match ContactHeader::deserialize_with_max_eid(&buffer, 1024) {
+            Err(nom::Err::Error(TcpclError::EidTooLong(60000))) => (),
+            _ => panic!("eid length over the cap must be an error"),
+        }
+        let mut decoder = ContactHeaderDecoder::new();
+        decoder.push(&buffer);
+        assert!(decoder.try_parse().is_none());
+        decoder.max_eid_len(1024);
+        match decoder.try_parse() {
+            Some(Err(TcpclError::EidTooLong(60000))) => (),
+            _ => panic!("eid length over the cap must be an error"),
+        }
+    }
+
+    #[test]
+    /// Test that a declared segment length is checked before any data arrives
+    fn test_segment_length_cap() {
+        let mut segment = XferSegment::new(END, 1, vec![0; 100]).serialize();
+        segment.truncate(segment.len() - 100);
+        match XferSegment::deserialize(&segment) {
+            Err(nom::Err::Incomplete(_)) => (),
+            _ => panic!("missing data must be incomplete"),
+        }
+        match XferSegment::deserialize_with_max(&segment, 99) {
+            Err(nom::Err::Error(TcpclError::SegmentTooLong(_))) => (),
+            _ => panic!("data length over the cap must be an error"),
+        }
+        let mut huge = XferSegment::new(END, 1, vec![]).serialize();
+        let length_offset = huge.len() - 8;
+        huge[length_offset..].copy_from_slice(&[0xff; 8]);
+        match XferSegment::deserialize(&huge) {
+            Err(nom::Err::Error(TcpclError::SegmentTooLong(_))) => (),
+            _ => panic!("data length over MAX_SEGMENT_LEN must be an error"),
+        }
+    }
+
+    #[test]
+    /// Test that every truncation of a Contact Header is reported as incomplete
+    fn test_truncated_header_incomplete() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).eid("localhost").unwrap();
+        let buffer = contact_header.serialize();
+        for length in 0..buffer.len() {
+            match ContactHeader::deserialize(&buffer[..length]) {
+                Err(nom::Err::Incomplete(_)) => (),
+                other => panic!("truncation to {} must be incomplete, got {:?}", length, other),
+            }
+        }
+    }
+
+    #[test]
+    /// Test reading Contact Headers from a reader without consuming following bytes
+    fn test_read_from() {
+        let mut first = ContactHeader::new();
+        first.keepalive(30).eid("localhost").unwrap();
+        let second = ContactHeader::new();
+        let mut buffer = first.serialize();
+        buffer.extend(second.serialize());
+        let mut cursor = std::io::Cursor::new(buffer);
+        assert_eq!(ContactHeader::read_from(&mut cursor).unwrap(), first);
+        assert_eq!(ContactHeader::read_from(&mut cursor).unwrap(), second);
+        match ContactHeader::read_from(&mut cursor) {
+            Err(TcpclError::Io(ref e)) if e.kind() == ErrorKind::UnexpectedEof => (),
+            other => panic!("reading past the end must fail, got {:?}", other),
+        }
+        let mut cursor = std::io::Cursor::new(vec![0u8; 26]);
+        match ContactHeader::read_from(&mut cursor) {
+            Err(TcpclError::BadMagic) => (),
+            other => panic!("invalid magic must be an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    /// Test that a short header of another version is rejected after its first 6 octets
+    fn test_read_from_short_header() {
+        let mut cursor = std::io::Cursor::new(vec![0x64, 0x74, 0x6e, 0x21, 0x03, 0x00]);
+        match ContactHeader::read_from(&mut cursor) {
+            Err(TcpclError::UnsupportedVersion(3)) => (),
+            other => panic!("version 3 must be rejected, got {:?}", other),
+        }
+        let mut cursor = std::io::Cursor::new(b"GET / ".to_vec());
+        match ContactHeader::read_from(&mut cursor) {
+            Err(TcpclError::BadMagic) => (),
+            other => panic!("invalid magic must be an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    /// Test that the eid length is checked before the eid is read
+    fn test_read_from_with_max_eid() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.eid("a".repeat(100)).unwrap();
+        let buffer = contact_header.serialize();
+        let mut cursor = std::io::Cursor::new(buffer[..CONTACT_HEADER_BASE_LENGTH + 2].to_vec());
+        match ContactHeader::read_from_with_max_eid(&mut cursor, 10) {
+            Err(TcpclError::EidTooLong(100)) => (),
+            other => panic!("eid over the maximum must be an error, got {:?}", other),
+        }
+        let mut cursor = std::io::Cursor::new(buffer);
+        assert_eq!(ContactHeader::read_from_with_max_eid(&mut cursor, 100).unwrap(),
+                   contact_header);
+    }
+
+    #[test]
+    /// Test parsing a Contact Header from a slice standing in for a memory map
+    fn test_parse_mmap() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).eid("dtn://capture/").unwrap();
+        let mut mapped = contact_header.serialize();
+        let length = mapped.len();
+        mapped.extend(&[0x04, 0x00]);
+        let (parsed, consumed) = parse_mmap(&mapped[..]).unwrap();
+        drop(mapped);
+        assert_eq!(parsed, contact_header);
+        assert_eq!(consumed, length);
+        match parse_mmap(&contact_header.serialize()[..length - 1]) {
+            Err(TcpclError::Incomplete) => (),
+            other => panic!("truncated slice must be incomplete, got {:?}", other),
+        }
+    }
+
+    /// In-memory stream reading from a fixed buffer and recording everything written
+    struct MockStream {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Test that both sides of a connection receive each other's Contact Header
+    fn test_contact_exchange() {
+        let mut server_header = ContactHeader::new();
+        server_header.flags(CAN_TLS).eid("server").unwrap();
+        let mut client_header = ContactHeader::new();
+        client_header.keepalive(30).eid("client").unwrap();
+
+        let mut server = MockStream {
+            input: std::io::Cursor::new(client_header.serialize()),
+            output: Vec::new(),
+        };
+        let mut client = MockStream {
+            input: std::io::Cursor::new(server_header.serialize()),
+            output: Vec::new(),
+        };
+        let from_client = perform_contact_exchange(&mut server, &server_header, 64).unwrap();
+        let from_server = perform_contact_exchange(&mut client, &client_header, 64).unwrap();
+        assert_eq!(from_client, client_header);
+        assert_eq!(from_server, server_header);
+        assert_eq!(server.output, client.input.into_inner());
+        assert_eq!(client.output, server.input.into_inner());
+    }
+
+    #[test]
+    /// Test that a peer with another version is rejected
+    fn test_contact_exchange_version() {
+        let mut peer = ContactHeader::new().serialize();
+        peer[4] = 0x05;
+        let mut stream = MockStream { input: std::io::Cursor::new(peer), output: Vec::new() };
+        let local = ContactHeader::new();
+        match perform_contact_exchange(&mut stream, &local, MAX_EID_LEN) {
+            Err(TcpclError::UnsupportedVersion(5)) => (),
+            other => panic!("version 5 must be rejected, got {:?}", other),
+        }
+        assert_eq!(stream.output, local.serialize());
+    }
+
+    #[test]
+    /// Test that the exchange rejects a peer eid over the given maximum
+    fn test_contact_exchange_max_eid() {
+        let mut peer = ContactHeader::new();
+        peer.eid("a".repeat(100)).unwrap();
+        let mut stream = MockStream {
+            input: std::io::Cursor::new(peer.serialize()),
+            output: Vec::new(),
+        };
+        match perform_contact_exchange(&mut stream, &ContactHeader::new(), 64) {
+            Err(TcpclError::EidTooLong(100)) => (),
+            other => panic!("eid over the maximum must be rejected, got {:?}", other),
+        }
+    }
+
+    /// In-memory stream with a read timeout, reporting `WouldBlock` once its input is empty
+    struct TimeoutStream {
+        input: std::io::Cursor<Vec<u8>>,
+        read_timeout: Option<Duration>,
+    }
+
+    impl Read for TimeoutStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(Error::new(ErrorKind::WouldBlock, "read timed out")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for TimeoutStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for TimeoutStream {
+        fn read_timeout(&self) -> std::io::Result<Option<Duration>> {
+            Ok(self.read_timeout)
+        }
+
+        fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+            self.read_timeout = timeout;
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Test that an incomplete Contact Header fails once the deadline has passed
+    fn test_contact_read_deadline() {
+        let session_timeout = Some(Duration::from_secs(30));
+        let mut stream = TimeoutStream {
+            input: std::io::Cursor::new(ContactHeader::new().serialize()[..10].to_vec()),
+            read_timeout: session_timeout,
+        };
+        {
+            let mut reader = DeadlineStream::new(&mut stream, Duration::from_secs(2)).unwrap();
+            match ContactHeader::read_from(&mut reader) {
+                Err(TcpclError::Io(ref e)) if e.kind() == ErrorKind::TimedOut => (),
+                other => panic!("incomplete contact header must time out, got {:?}", other),
+            }
+            assert!(reader.stream.read_timeout.unwrap() <= Duration::from_secs(2));
+        }
+        assert_eq!(stream.read_timeout, session_timeout);
+
+        let mut reader = DeadlineStream::new(&mut stream, Duration::from_secs(0)).unwrap();
+        match reader.read(&mut [0; 1]) {
+            Err(ref e) if e.kind() == ErrorKind::TimedOut => (),
+            other => panic!("read after the deadline must time out, got {:?}", other),
+        }
+    }
+
+    fn negotiate_with(local: (u16, ContactHeaderFlags), peer: (u16, ContactHeaderFlags))
+                      -> NegotiatedParams {
+        let mut local_header = ContactHeader::new();
+        local_header.keepalive(local.0).flags(local.1);
+        let mut peer_header = ContactHeader::new();
+        peer_header.keepalive(peer.0).flags(peer.1).segment_mru(1024).transfer_mru(4096);
+        local_header.negotiate(&peer_header)
+    }
+
+    #[test]
+    /// Test that the smaller keepalive is chosen and 0 disables keepalives
+    fn test_negotiate_keepalive() {
+        let empty = ContactHeaderFlags::empty();
+        assert_eq!(negotiate_with((30, empty), (60, empty)).keepalive(), 30);
+        assert_eq!(negotiate_with((60, empty), (30, empty)).keepalive(), 30);
+        assert_eq!(negotiate_with((0, empty), (30, empty)).keepalive(), 0);
+        assert_eq!(negotiate_with((30, empty), (0, empty)).keepalive(), 0);
+        let params = negotiate_with((30, empty), (60, empty));
+        assert_eq!(params.segment_mru(), 1024);
+        assert_eq!(params.transfer_mru(), 4096);
+    }
+
+    #[test]
+    /// Test that TLS is only used if both sides agree
+    fn test_negotiate_tls() {
+        let empty = ContactHeaderFlags::empty();
+        assert!(negotiate_with((30, CAN_TLS), (30, CAN_TLS)).use_tls());
+        assert!(!negotiate_with((30, CAN_TLS), (30, empty)).use_tls());
+        assert!(!negotiate_with((30, empty), (30, CAN_TLS)).use_tls());
+        assert!(!negotiate_with((30, empty), (30, empty)).use_tls());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    /// Test that a Contact Header survives a JSON roundtrip with flag names and eid
+    fn test_serde_json_roundtrip() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.flags(CAN_TLS).keepalive(30).eid("localhost").unwrap();
+        let json = serde_json::to_value(&contact_header).unwrap();
+        assert_eq!(json["flags"], serde_json::json!(["CAN_TLS"]));
+        assert_eq!(json["eid"], serde_json::json!("localhost"));
+        let parsed: ContactHeader = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, contact_header);
+
+        let json = serde_json::to_value(ContactHeader::new()).unwrap();
+        assert_eq!(json["flags"], serde_json::json!([]));
+        assert_eq!(json["eid"], serde_json::Value::Null);
+        assert!(serde_json::from_str::<ContactHeaderFlags>("[\"NO_SUCH_FLAG\"]").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    /// Test that serde validates the eid length and defaults a missing variant
+    fn test_serde_json_validation() {
+        let mut json = serde_json::to_value(ContactHeader::new()).unwrap();
+        json.as_object_mut().unwrap().remove("variant");
+        let parsed: ContactHeader = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(parsed, ContactHeader::new());
+
+        json["eid"] = serde_json::json!("a".repeat(MAX_EID_LEN + 1));
+        let err = serde_json::from_value::<ContactHeader>(json).unwrap_err();
+        assert!(err.to_string().contains("eid of 65536 bytes is to long"));
+    }
+
+    #[test]
+    /// Test the send and expiry thresholds of the keepalive timer
+    fn test_keepalive_timer() {
+        let start = Instant::now();
+        let mut timer = KeepaliveTimer::new(30, start);
+        assert!(!timer.should_send(start + Duration::from_secs(29)));
+        assert!(timer.should_send(start + Duration::from_secs(30)));
+        assert!(!timer.is_expired(start + Duration::from_secs(59)));
+        assert!(timer.is_expired(start + Duration::from_secs(60)));
+
+        let later = start + Duration::from_secs(40);
+        timer.record_sent(later);
+        timer.record_received(later);
+        assert!(!timer.should_send(later + Duration::from_secs(29)));
+        assert!(timer.should_send(later + Duration::from_secs(30)));
+        assert!(!timer.is_expired(later + Duration::from_secs(59)));
+        assert!(timer.is_expired(later + Duration::from_secs(60)));
+    }
+
+    #[test]
+    /// Test that an interval of 0 disables the keepalive timer
+    fn test_keepalive_timer_disabled() {
+        let start = Instant::now();
+        let timer = KeepaliveTimer::new(0, start);
+        let much_later = start + Duration::from_secs(3600);
+        assert!(!timer.should_send(much_later));
+        assert!(!timer.is_expired(much_later));
+    }
+
+    #[test]
+    /// Test reassembling a transfer of two segments and an empty transfer
+    fn test_reassemble_transfer() {
+        let mut reassembler = TransferReassembler::new(16);
+        assert_eq!(reassembler.push(XferSegment::new(START, 1, vec![1, 2])).unwrap(), None);
+        assert_eq!(reassembler.in_progress(), 1);
+        assert_eq!(reassembler.push(XferSegment::new(END, 1, vec![3])).unwrap(),
+                   Some(vec![1, 2, 3]));
+        assert_eq!(reassembler.in_progress(), 0);
+        assert_eq!(reassembler.push(XferSegment::new(START | END, 2, vec![])).unwrap(),
+                   Some(vec![]));
+    }
+
+    #[test]
+    /// Test the errors of the reassembler
+    fn test_reassemble_errors() {
+        let mut reassembler = TransferReassembler::new(4);
+        reassembler.push(XferSegment::new(START, 1, vec![1])).unwrap();
+        match reassembler.push(XferSegment::new(START, 1, vec![2])) {
+            Err(TcpclError::TransferAlreadyStarted(1)) => (),
+            other => panic!("START after START must be an error, got {:?}", other),
+        }
+        match reassembler.push(XferSegment::new(END, 2, vec![2])) {
+            Err(TcpclError::TransferNotStarted(2)) => (),
+            other => panic!("data before START must be an error, got {:?}", other),
+        }
+        match reassembler.push(XferSegment::new(XferSegmentFlags::empty(), 1, vec![0; 4])) {
+            Err(TcpclError::TransferTooLong(1)) => (),
+            other => panic!("exceeding the transfer mru must be an error, got {:?}", other),
+        }
+        assert_eq!(reassembler.in_progress(), 0);
+    }
+
+    #[test]
+    /// Test that a START beyond the maximum number of transfers in progress is rejected
+    fn test_reassemble_too_many_transfers() {
+        let mut reassembler = TransferReassembler::new(4);
+        reassembler.max_in_progress(2);
+        reassembler.push(XferSegment::new(START, 0, vec![1])).unwrap();
+        reassembler.push(XferSegment::new(START, 1, vec![1])).unwrap();
+        match reassembler.push(XferSegment::new(START, 2, vec![1])) {
+            Err(TcpclError::TooManyTransfers(2)) => (),
+            other => panic!("START beyond the maximum must be an error, got {:?}", other),
+        }
+        assert_eq!(reassembler.in_progress(), 2);
+        reassembler.push(XferSegment::new(END, 0, vec![2])).unwrap();
+        reassembler.push(XferSegment::new(START, 2, vec![1])).unwrap();
+    }
+
+    #[test]
+    /// Test that transfer ids start at 0 and are counted per allocator
+    fn test_transfer_id_allocator() {
+        let mut first = TransferIdAllocator::new();
+        let mut second = TransferIdAllocator::new();
+        assert_eq!(first.next().unwrap(), 0);
+        assert_eq!(first.next().unwrap(), 1);
+        assert_eq!(first.next().unwrap(), 2);
+        assert_eq!(second.next().unwrap(), 0);
+    }
+
+    #[test]
+    /// Test that the allocator fails instead of wrapping around
+    fn test_transfer_id_exhausted() {
+        let mut allocator = TransferIdAllocator { next_id: u64::MAX, exhausted: false };
+        assert_eq!(allocator.next().unwrap(), u64::MAX);
+        match allocator.next() {
+            Err(TcpclError::TransferIdsExhausted) => (),
+            other => panic!("exhausted allocator must fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    /// Test that the compact form never carries the eid
+    fn test_serialize_compact() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30);
+        assert_eq!(contact_header.serialize_compact().len(), 26);
+        assert_eq!(contact_header.serialize_compact(), contact_header.serialize());
+        contact_header.eid("dtn://a-rather-long-node-name/").unwrap();
+        let buffer = contact_header.serialize_compact();
+        assert_eq!(buffer.len(), 26);
+        assert_eq!(&buffer[24..], &[0x00, 0x00]);
+    }
+
+    #[test]
+    /// Test the delay computed for a segment at a given rate
+    fn test_rate_limiter() {
+        let now = Instant::now();
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.next_send_time(500, now), now + Duration::from_millis(500));
+        assert_eq!(limiter.next_send_time(3000, now), now + Duration::from_secs(3));
+        assert_eq!(limiter.next_send_time(0, now), now);
+        assert_eq!(RateLimiter::new(0).next_send_time(3000, now), now);
+    }
+
+    #[test]
+    /// Test that dtn eids with and without trailing slash normalize to the same value
+    fn test_normalized_eid() {
+        let mut contact_header = ContactHeader::new();
+        assert_eq!(contact_header.normalized_eid(), None);
+        contact_header.eid("dtn://node").unwrap();
+        assert_eq!(contact_header.normalized_eid(), Some("dtn://node/".to_string()));
+        contact_header.eid("dtn://node/").unwrap();
+        assert_eq!(contact_header.normalized_eid(), Some("dtn://node/".to_string()));
+        contact_header.eid("dtn://node/service").unwrap();
+        assert_eq!(contact_header.normalized_eid(), Some("dtn://node/service".to_string()));
+        contact_header.eid("ipn:1.0").unwrap();
+        assert_eq!(contact_header.normalized_eid(), Some("ipn:1.0".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "bench-internal")]
+    /// Test that the instrumented parser records every stage
+    fn test_deserialize_timed() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(30).eid("localhost").unwrap();
+        let buffer = contact_header.serialize();
+        let start = Instant::now();
+        let timed = ContactHeader::deserialize_timed(&buffer);
+        let elapsed = start.elapsed();
+        match (timed, ContactHeader::deserialize(&buffer)) {
+            (Ok((rest, (parsed, timing))), Ok((expected_rest, expected))) => {
+                assert_eq!(rest, expected_rest);
+                assert_eq!(parsed, expected);
+                for phase in &[timing.magic(), timing.base_fields(), timing.eid()] {
+                    assert!(*phase <= elapsed);
+                }
+                assert!(timing.total() <= elapsed);
+            }
+            _ => panic!("failed to parse contact header"),
+        }
+    }
+
+    /// Contact Header declaring a 10 octet eid followed by only 5 octets
+    fn short_eid_header() -> Vec<u8> {
+        let mut buffer = ContactHeader::new().serialize();
+        buffer.truncate(CONTACT_HEADER_BASE_LENGTH);
+        buffer.extend(&[0x00, 0x0a]);
+        buffer.extend(b"dtn:/");
+        buffer
+    }
+
+    #[test]
+    /// Test that a short eid in a stream asks for more data
+    fn test_eid_length_incomplete() {
+        match ContactHeader::deserialize(&short_eid_header()) {
+            Err(nom::Err::Incomplete(_)) => (),
+            _ => panic!("short eid must be incomplete"),
+        }
+    }
+
+    #[test]
+    /// Test that a short eid in a complete frame is an error
+    fn test_eid_length_mismatch() {
+        let message = short_eid_header();
+        let mut buffer = vec![0x00, 0x00, 0x00, message.len() as u8];
+        buffer.extend(message);
+        match ContactHeader::deserialize_framed(&buffer) {
+            Err(nom::Err::Error(TcpclError::EidLengthMismatch)) => (),
+            _ => panic!("short eid in a frame must be an eid length mismatch"),
+        }
+    }
+
+    #[test]
+    /// Test enforcing a maximum keepalive below, at and above the cap
+    fn test_enforce_max_keepalive() {
+        assert!(keepalive_negotiation(30, 60).enforce_max_keepalive(60).is_ok());
+        assert!(keepalive_negotiation(60, 60).enforce_max_keepalive(60).is_ok());
+        match keepalive_negotiation(3600, 3600).enforce_max_keepalive(60) {
+            Err(TcpclError::KeepaliveTooLong { interval: 3600, max: 60 }) => (),
+            _ => panic!("keepalive above the maximum must be an error"),
+        }
+        assert!(keepalive_negotiation(0, 3600).enforce_max_keepalive(60).is_ok());
+    }
+
+    fn fingerprint_header(keepalive: u16, eid: &str) -> ContactHeader {
+        let mut contact_header = ContactHeader::new();
+        contact_header.keepalive(keepalive).segment_mru(1024).transfer_mru(4096)
+            .eid(eid).unwrap();
+        contact_header
+    }
+
+    #[test]
+    /// Test that identical parameters give identical fingerprints
+    fn test_parameter_fingerprint() {
+        let local = fingerprint_header(30, "dtn://a/");
+        let remote = fingerprint_header(60, "dtn://b/");
+        let fingerprint = parameter_fingerprint(&local, &remote);
+        assert_eq!(fingerprint,
+                   parameter_fingerprint(&fingerprint_header(30, "dtn://a/"),
+                                         &fingerprint_header(60, "dtn://b/")));
+        let mut other_version = fingerprint_header(30, "dtn://a/");
+        other_version.version = 3;
+        assert_eq!(fingerprint, parameter_fingerprint(&other_version, &remote));
+    }
+
+    #[test]
+    /// Test that a different negotiated keepalive changes the fingerprint
+    fn test_parameter_fingerprint_keepalive() {
+        let remote = fingerprint_header(60, "dtn://b/");
+        assert_ne!(parameter_fingerprint(&fingerprint_header(30, "dtn://a/"), &remote),
+                   parameter_fingerprint(&fingerprint_header(20, "dtn://a/"), &remote));
+    }
+
+    #[test]
+    /// Test that both peers compute the same fingerprint for their session
+    fn test_parameter_fingerprint_symmetric() {
+        let a = fingerprint_header(30, "dtn://a/");
+        let mut b = fingerprint_header(60, "dtn://b/");
+        b.segment_mru(2048);
+        assert_eq!(parameter_fingerprint(&a, &b), parameter_fingerprint(&b, &a));
+    }
+
+    #[test]
+    /// Test that an eid with invalid UTF-8 is a parse error instead of a panic
+    fn test_invalid_utf8_eid() {
+        let mut buffer = ContactHeader::new().serialize();
+        buffer.truncate(CONTACT_HEADER_BASE_LENGTH);
+        buffer.extend(&[0x00, 0x04, 0xff, 0xff, 0xff, 0xff]);
+        match ContactHeader::deserialize(&buffer) {
+            Err(nom::Err::Error(_)) => (),
+            _ => panic!("invalid eid must be a parse error"),
+        }
+        match ContactHeader::deserialize(&buffer[..buffer.len() - 1]) {
+            Err(nom::Err::Incomplete(_)) => (),
+            _ => panic!("short eid must be incomplete"),
+        }
+    }
+
+    #[test]
+    /// Test that the getters return the values set before serialization
+    fn test_getters_roundtrip() {
+        let mut contact_header = ContactHeader::new();
+        contact_header.flags(CAN_TLS).keepalive(30).segment_mru(1024).transfer_mru(4096)
+            .eid("localhost").unwrap();
+        let buffer = contact_header.serialize();
+        let parsed = match ContactHeader::deserialize(&buffer) {
+            Ok((_, parsed)) => parsed,
+            _ => panic!("failed to parse contact header"),
+        };
+        assert_eq!(parsed.version(), 4);
+        assert_eq!(parsed.get_flags(), CAN_TLS);
+        assert_eq!(parsed.get_keepalive(), 30);
+        assert_eq!(parsed.get_segment_mru(), 1024);
+        assert_eq!(parsed.get_transfer_mru(), 4096);
+        assert_eq!(parsed.get_eid(), Some("localhost"));
+        assert_eq!(ContactHeader::new().get_eid(), None);
+    }
+
+    #[test]
+    /// Test serializing and parsing a SESS_INIT without Extension Items
+    fn test_sess_init_roundtrip() {
+        let mut sess_init = SessInit::new();
+        sess_init.keepalive_interval(30).segment_mru(1024).transfer_mru(4096)
+            .node_id("dtn://node/").unwrap();
+        let mut buffer = sess_init.serialize();
+        assert_eq!(buffer[0], 0x07);
+        assert_eq!(buffer.len(), 21 + 11 + 4);
+        assert_eq!(&buffer[buffer.len() - 4..], &[0x00, 0x00, 0x00, 0x00]);
+        buffer.push(0x04);
+        match SessInit::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert_eq!(rest, &[0x04]);
+                assert_eq!(parsed, sess_init);
+                assert_eq!(parsed.get_node_id(), "dtn://node/");
+                assert!(parsed.get_extensions().is_empty());
+            }
+            _ => panic!("failed to parse SESS_INIT"),
+        }
+    }
+
+    #[test]
+    /// Test serializing and parsing a SESS_INIT with an Extension Item
+    fn test_sess_init_extension_roundtrip() {
+        let mut sess_init = SessInit::new();
+        sess_init.add_extension(ExtensionItem::new(CRITICAL, 0x0a, vec![1, 2, 3]).unwrap())
+            .unwrap();
+        let buffer = sess_init.serialize();
+        assert_eq!(&buffer[21..], &[0x00, 0x00, 0x00, 0x08, 0x01, 0x00, 0x0a, 0x00, 0x03, 1, 2, 3]);
+        match SessInit::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, sess_init);
+            }
+            _ => panic!("failed to parse SESS_INIT"),
+        }
+        match SessInit::deserialize(&buffer[..buffer.len() - 1]) {
+            Err(nom::Err::Incomplete(_)) => (),
+            _ => panic!("truncated SESS_INIT must be incomplete"),
+        }
+    }
+
+    #[test]
+    /// Test that repeated Extension Items of the same type are all preserved in order
+    fn test_sess_init_duplicate_extensions() {
+        let mut sess_init = SessInit::new();
+        for &(item_type, value) in &[(0x0a, 1), (0x0b, 2), (0x0a, 3)] {
+            let item = ExtensionItem::new(ExtensionFlags::empty(), item_type, vec![value]);
+            sess_init.add_extension(item.unwrap()).unwrap();
+        }
+        let buffer = sess_init.serialize();
+        let parsed = match SessInit::deserialize(&buffer) {
+            Ok((_, parsed)) => parsed,
+            _ => panic!("failed to parse SESS_INIT"),
+        };
+        assert_eq!(parsed.get_extensions().len(), 3);
+        let items = parsed.extensions_of_type(0x0a);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].value(), &[1]);
+        assert_eq!(items[1].value(), &[3]);
+        assert!(parsed.extensions_of_type(0x0c).is_empty());
+    }
+
+    #[test]
+    /// Test that Extension Items beyond MAX_EXTENSIONS_LEN in total are rejected
+    fn test_sess_init_extensions_too_long() {
+        let item = ExtensionItem::new(ExtensionFlags::empty(), 0x0a, vec![0; 65535]).unwrap();
+        let mut sess_init = SessInit::new();
+        for _ in 0..15 {
+            sess_init.add_extension(item.clone()).unwrap();
+        }
+        match sess_init.add_extension(item.clone()) {
+            Err(TcpclError::ExtensionsTooLong(len)) => assert_eq!(len, 16 * 65540),
+            other => panic!("extensions over MAX_EXTENSIONS_LEN must be an error, got {:?}",
+                            other),
+        }
+        assert_eq!(sess_init.get_extensions().len(), 15);
+        let (_, parsed) = SessInit::deserialize(&sess_init.serialize()).unwrap();
+        assert_eq!(parsed, sess_init);
+        assert!(ExtensionItem::serialize_list(&vec![item; 16]).is_err());
+    }
+
+    #[test]
+    /// Test that a KEEPALIVE consumes exactly its message type octet
+    fn test_keepalive() {
+        assert_eq!(Keepalive.serialize(), vec![0x04]);
+        let buffer = [0x04, 0x04];
+        match Keepalive::deserialize(&buffer) {
+            Ok((rest, keepalive)) => {
+                assert_eq!(rest, &[0x04]);
+                assert_eq!(keepalive, Keepalive);
+            }
+            _ => panic!("failed to parse KEEPALIVE"),
+        }
+        assert!(Keepalive::deserialize(&[0x07]).is_err());
+    }
+
+    #[test]
+    /// Test serializing and parsing a SESS_TERM reply
+    fn test_sess_term_roundtrip() {
+        let sess_term = SessTerm::new(REPLY, SessTermReason::IdleTimeout);
+        let buffer = sess_term.serialize();
+        assert_eq!(buffer, vec![0x05, 0x01, 0x01]);
+        match SessTerm::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, sess_term);
+                assert!(parsed.flags().contains(REPLY));
+                assert_eq!(parsed.reason(), SessTermReason::IdleTimeout);
+            }
+            _ => panic!("failed to parse SESS_TERM"),
+        }
+    }
+
+    #[test]
+    /// Test that an unknown reason code is a parse error
+    fn test_sess_term_unknown_reason() {
+        assert!(SessTerm::deserialize(&[0x05, 0x00, 0x06]).is_err());
+    }
+
+    #[test]
+    /// Test that reserved SESS_TERM flags are reported separately and not serialized
+    fn test_sess_term_reserved_flags() {
+        let (_, parsed) = SessTerm::deserialize(&[0x05, 0x81, 0x02]).unwrap();
+        assert_eq!(parsed.flags(), REPLY);
+        assert_eq!(parsed.reserved_flags(), 0x80);
+        assert_eq!(parsed.serialize(), vec![0x05, 0x01, 0x02]);
+    }
+
+    #[test]
+    /// Test serializing and parsing a single segment transfer with an Extension Item
+    fn test_xfer_segment_single() {
+        let mut segment = XferSegment::new(START | END, 7, vec![0xde, 0xad]);
+        segment.add_extension(ExtensionItem::new(ExtensionFlags::empty(), 0x01, vec![0x02]).unwrap())
+            .unwrap();
+        let buffer = segment.serialize();
+        assert_eq!(buffer, vec![0x01, 0x03, 0, 0, 0, 0, 0, 0, 0, 7,
+                                0, 0, 0, 6, 0x00, 0x00, 0x01, 0x00, 0x01, 0x02,
+                                0, 0, 0, 0, 0, 0, 0, 2, 0xde, 0xad]);
+        match XferSegment::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, segment);
+            }
+            _ => panic!("failed to parse XFER_SEGMENT"),
+        }
+    }
+
+    #[test]
+    /// Test serializing and parsing a middle segment, which carries no Extension Items
+    fn test_xfer_segment_middle() {
+        let segment = XferSegment::new(XferSegmentFlags::empty(), 7, vec![1, 2, 3]);
+        let buffer = segment.serialize();
+        assert_eq!(buffer, vec![0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 7,
+                                0, 0, 0, 0, 0, 0, 0, 3, 1, 2, 3]);
+        match XferSegment::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, segment);
+                assert!(parsed.extensions().is_empty());
+            }
+            _ => panic!("failed to parse XFER_SEGMENT"),
+        }
+    }
+
+    #[test]
+    /// Test that reserved XFER_SEGMENT flags are reported separately and not serialized
+    fn test_xfer_segment_reserved_flags() {
+        let mut buffer = XferSegment::new(END, 7, vec![1]).serialize();
+        buffer[1] |= 0x80;
+        let (_, parsed) = XferSegment::deserialize(&buffer).unwrap();
+        assert_eq!(parsed.flags(), END);
+        assert_eq!(parsed.reserved_flags(), 0x80);
+        buffer[1] &= !0x80;
+        assert_eq!(parsed.serialize(), buffer);
+    }
+
+    #[test]
+    /// Test that Extension Items can only be added to a segment with START
+    fn test_xfer_segment_extension_without_start() {
+        let mut segment = XferSegment::new(END, 7, vec![1]);
+        let item = ExtensionItem::new(ExtensionFlags::empty(), 0x01, vec![]).unwrap();
+        match segment.add_extension(item) {
+            Err(TcpclError::ExtensionsWithoutStart(7)) => (),
+            other => panic!("extension without START must be an error, got {:?}", other),
+        }
+        assert!(segment.extensions().is_empty());
+    }
+
+    #[test]
+    /// Test that the data of a borrowed segment points into the parsed buffer
+    fn test_parse_segment_borrowed() {
+        let segment = XferSegment::new(START, 3, vec![1, 2, 3, 4]);
+        let buffer = segment.serialize();
+        match parse_segment_borrowed(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed.data(), &[1, 2, 3, 4]);
+                assert_eq!(parsed.data().as_ptr(), buffer[buffer.len() - 4..].as_ptr());
+                assert_eq!(parsed.transfer_id(), 3);
+                assert_eq!(parsed.into_owned(), segment);
+            }
+            _ => panic!("failed to parse XFER_SEGMENT"),
+        }
+    }
+
+    #[test]
+    /// Test serializing and parsing a XFER_ACK
+    fn test_xfer_ack_roundtrip() {
+        let ack = XferAck::new(START, 7, 1024);
+        let buffer = ack.serialize();
+        assert_eq!(buffer, vec![0x02, 0x02, 0, 0, 0, 0, 0, 0, 0, 7,
+                                0, 0, 0, 0, 0, 0, 0x04, 0x00]);
+        match XferAck::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, ack);
+            }
+            _ => panic!("failed to parse XFER_ACK"),
+        }
+    }
+
+    #[test]
+    /// Test that reserved XFER_ACK flags are reported separately and not serialized
+    fn test_xfer_ack_reserved_flags() {
+        let (_, parsed) = XferAck::deserialize(&[0x02, 0x41, 0, 0, 0, 0, 0, 0, 0, 7,
+                                                  0, 0, 0, 0, 0, 0, 0, 10]).unwrap();
+        assert_eq!(parsed.flags(), END);
+        assert_eq!(parsed.reserved_flags(), 0x40);
+        assert_eq!(parsed.serialize(), XferAck::new(END, 7, 10).serialize());
+    }
+
+    #[test]
+    /// Test that bytes following a XFER_ACK are left in the remaining input
+    fn test_xfer_ack_trailing_byte() {
+        let mut buffer = XferAck::new(END, 1, 10).serialize();
+        buffer.push(0xff);
+        match XferAck::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert_eq!(rest, &[0xff]);
+                assert_eq!(parsed.acknowledged_length(), 10);
+            }
+            _ => panic!("failed to parse XFER_ACK"),
+        }
+    }
+
+    #[test]
+    /// Test serializing and parsing a XFER_REFUSE
+    fn test_xfer_refuse_roundtrip() {
+        let refuse = XferRefuse::new(XferRefuseReason::NoResources, 7);
+        let buffer = refuse.serialize();
+        assert_eq!(buffer, vec![0x03, 0x02, 0, 0, 0, 0, 0, 0, 0, 7]);
+        match XferRefuse::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, refuse);
+            }
+            _ => panic!("failed to parse XFER_REFUSE"),
+        }
+    }
+
+    #[test]
+    /// Test that an unknown reason code is rejected and named in the error
+    fn test_xfer_refuse_unknown_reason() {
+        assert!(XferRefuse::deserialize(&[0x03, 0x07, 0, 0, 0, 0, 0, 0, 0, 7]).is_err());
+        match XferRefuseReason::from_u8_strict(0x07) {
+            Err(err @ TcpclError::UnknownRefuseReason(0x07)) => {
+                assert!(err.to_string().contains("0x07"))
+            }
+            _ => panic!("unknown reason code must be an error"),
+        }
+    }
+
+    #[test]
+    /// Test serializing and parsing a MSG_REJECT for an unknown message type
+    fn test_msg_reject_roundtrip() {
+        let reject = MsgReject::unknown_type(0x99);
+        assert_eq!(reject.reason(), MsgRejectReason::MessageTypeUnknown);
+        let buffer = reject.serialize();
+        assert_eq!(buffer, vec![0x06, 0x01, 0x99]);
+        match MsgReject::deserialize(&buffer) {
+            Ok((rest, parsed)) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, reject);
+                assert_eq!(parsed.rejected_message_header(), 0x99);
+            }
+            _ => panic!("failed to parse MSG_REJECT"),
+        }
+        assert!(MsgReject::deserialize(&[0x06, 0x00, 0x99]).is_err());
+    }
+
+    #[test]
+    /// Test parsing consecutive messages of different types from one buffer
+    fn test_message_dispatch() {
+        let ack = XferAck::new(END, 3, 42);
+        let mut buffer = Message::Keepalive(Keepalive).serialize();
+        buffer.extend(Message::XferAck(ack).serialize());
+        let rest = match Message::deserialize(&buffer) {
+            Ok((rest, Message::Keepalive(_))) => rest,
+            _ => panic!("expected KEEPALIVE"),
+        };
+        assert_eq!(rest.len(), 18);
+        match Message::deserialize(rest) {
+            Ok((rest, Message::XferAck(parsed))) => {
+                assert!(rest.is_empty());
+                assert_eq!(parsed, ack);
+            }
+            _ => panic!("expected XFER_ACK"),
+        }
+    }
+
+    #[test]
+    /// Test that an unknown message type is reported with its type octet
+    fn test_message_unknown_type() {
+        match Message::deserialize(&[0x99, 0x00]) {
+            Err(nom::Err::Error(TcpclError::UnknownMessageType(0x99))) => (),
+            _ => panic!("unknown message type must be an error"),
+        }
+    }
 }